@@ -29,6 +29,42 @@ pub(crate) fn generate(service: &Service, proto: &str) -> TokenStream {
                 Self { inner }
             }
 
+            /// Run every request through `interceptor` before sending it.
+            pub fn with_interceptor(inner: T, interceptor: impl Into<tonic::Interceptor>) -> Self {
+                let inner = tonic::client::Grpc::with_interceptor(inner, interceptor);
+                Self { inner }
+            }
+
+            /// Compress requests with `gzip`.
+            ///
+            /// This requires the server to support it otherwise it might respond with an
+            /// error.
+            pub fn send_gzip(mut self) -> Self {
+                self.inner = self.inner.send_gzip();
+                self
+            }
+
+            /// Enable decompressing responses with `gzip`.
+            pub fn accept_gzip(mut self) -> Self {
+                self.inner = self.inner.accept_gzip();
+                self
+            }
+
+            /// Compress requests with `encoding`.
+            ///
+            /// This requires the server to support it otherwise it might respond with an
+            /// error.
+            pub fn send_compressed(mut self, encoding: tonic::codec::CompressionEncoding) -> Self {
+                self.inner = self.inner.send_compressed(encoding);
+                self
+            }
+
+            /// Enable decompressing responses with `encoding`.
+            pub fn accept_compressed(mut self, encoding: tonic::codec::CompressionEncoding) -> Self {
+                self.inner = self.inner.accept_compressed(encoding);
+                self
+            }
+
             /// Check if the service is ready.
             pub async fn ready(&mut self) -> Result<(), tonic::Status> {
                 self.inner.ready().await.map_err(|e| {
@@ -24,12 +24,50 @@ pub(crate) fn generate(service: &Service, proto_path: &str) -> TokenStream {
         #[doc(hidden)]
         pub struct #server_service<T: #server_trait> {
             inner: Arc<T>,
+            accept_compression_encodings: tonic::codec::EnabledCompressionEncodings,
+            send_compression_encodings: tonic::codec::EnabledCompressionEncodings,
+            interceptor: Option<tonic::Interceptor>,
         }
 
         impl<T: #server_trait> #server_service<T> {
             pub fn new(inner: T) -> Self {
                 let inner = Arc::new(inner);
-                Self { inner }
+                Self {
+                    inner,
+                    accept_compression_encodings: Default::default(),
+                    send_compression_encodings: Default::default(),
+                    interceptor: None,
+                }
+            }
+
+            /// Run every request through `interceptor` before handing it to the service.
+            pub fn with_interceptor(inner: T, interceptor: impl Into<tonic::Interceptor>) -> Self {
+                Self {
+                    interceptor: Some(interceptor.into()),
+                    ..Self::new(inner)
+                }
+            }
+
+            /// Enable decompressing requests with `gzip`.
+            pub fn accept_gzip(self) -> Self {
+                self.accept_compressed(tonic::codec::CompressionEncoding::gzip())
+            }
+
+            /// Compress responses with `gzip`, if the client supports it.
+            pub fn send_gzip(self) -> Self {
+                self.send_compressed(tonic::codec::CompressionEncoding::gzip())
+            }
+
+            /// Enable decompressing requests with `encoding`.
+            pub fn accept_compressed(mut self, encoding: tonic::codec::CompressionEncoding) -> Self {
+                self.accept_compression_encodings.enable(encoding);
+                self
+            }
+
+            /// Compress responses with `encoding`, if the client supports it.
+            pub fn send_compressed(mut self, encoding: tonic::codec::CompressionEncoding) -> Self {
+                self.send_compression_encodings.enable(encoding);
+                self
             }
         }
 
@@ -44,6 +82,9 @@ pub(crate) fn generate(service: &Service, proto_path: &str) -> TokenStream {
 
             fn call(&mut self, req: http::Request<HyperBody>) -> Self::Future {
                 let inner = self.inner.clone();
+                let accept_compression_encodings = self.accept_compression_encodings.clone();
+                let send_compression_encodings = self.send_compression_encodings.clone();
+                let interceptor = self.interceptor.clone();
 
                 match req.uri().path() {
                     #methods
@@ -62,7 +103,12 @@ pub(crate) fn generate(service: &Service, proto_path: &str) -> TokenStream {
         impl<T: #server_trait> Clone for #server_service<T> {
             fn clone(&self) -> Self {
                 let inner = self.inner.clone();
-                Self { inner }
+                Self {
+                    inner,
+                    accept_compression_encodings: self.accept_compression_encodings.clone(),
+                    send_compression_encodings: self.send_compression_encodings.clone(),
+                    interceptor: self.interceptor.clone(),
+                }
             }
         }
 
@@ -249,7 +295,12 @@ fn generate_unary(
         let fut = async move {
             let method = #service_ident(inner);
             let codec = tonic::codec::ProstCodec::default();
-            let mut grpc = tonic::server::Grpc::new(codec);
+            let grpc = match interceptor {
+                Some(interceptor) => tonic::server::Grpc::with_interceptor(codec, interceptor),
+                None => tonic::server::Grpc::new(codec),
+            };
+            let mut grpc = grpc
+                .apply_compression_config(accept_compression_encodings, send_compression_encodings);
             let res = grpc.unary(method, req).await;
             Ok(res)
         };
@@ -292,7 +343,12 @@ fn generate_server_streaming(
         let fut = async move {
             let method = #service_ident(inner);
             let codec = tonic::codec::ProstCodec::default();
-            let mut grpc = tonic::server::Grpc::new(codec);
+            let grpc = match interceptor {
+                Some(interceptor) => tonic::server::Grpc::with_interceptor(codec, interceptor),
+                None => tonic::server::Grpc::new(codec),
+            };
+            let mut grpc = grpc
+                .apply_compression_config(accept_compression_encodings, send_compression_encodings);
             let res = grpc.server_streaming(method, req).await;
             Ok(res)
         };
@@ -333,7 +389,12 @@ fn generate_client_streaming(
         let fut = async move {
             let method = #service_ident(inner);
             let codec = tonic::codec::ProstCodec::default();
-            let mut grpc = tonic::server::Grpc::new(codec);
+            let grpc = match interceptor {
+                Some(interceptor) => tonic::server::Grpc::with_interceptor(codec, interceptor),
+                None => tonic::server::Grpc::new(codec),
+            };
+            let mut grpc = grpc
+                .apply_compression_config(accept_compression_encodings, send_compression_encodings);
             let res = grpc.client_streaming(method, req).await;
             Ok(res)
         };
@@ -376,7 +437,12 @@ fn generate_streaming(
         let fut = async move {
             let method = #service_ident(inner);
             let codec = tonic::codec::ProstCodec::default();
-            let mut grpc = tonic::server::Grpc::new(codec);
+            let grpc = match interceptor {
+                Some(interceptor) => tonic::server::Grpc::with_interceptor(codec, interceptor),
+                None => tonic::server::Grpc::new(codec),
+            };
+            let mut grpc = grpc
+                .apply_compression_config(accept_compression_encodings, send_compression_encodings);
             let res = grpc.streaming(method, req).await;
             Ok(res)
         };
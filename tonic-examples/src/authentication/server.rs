@@ -4,8 +4,7 @@ pub mod pb {
 
 use pb::{EchoRequest, EchoResponse};
 use std::collections::VecDeque;
-use tonic::{body::BoxBody, transport::Server, Request, Response, Status, Streaming};
-use tower::Service;
+use tonic::{transport::Server, Code, Request, Response, Status, Streaming};
 
 type EchoResult<T> = Result<Response<T>, Status>;
 type Stream = VecDeque<Result<EchoResponse, Status>>;
@@ -46,41 +45,27 @@ impl pb::server::Echo for EchoServer {
     }
 }
 
+fn check_auth(request: Request<()>) -> Result<Request<()>, Status> {
+    let authed = match request.metadata().get("authorization") {
+        Some(token) => *token == "Bearer some-secret-token",
+        None => false,
+    };
+
+    if authed {
+        Ok(request)
+    } else {
+        Err(Status::new(Code::Unauthenticated, "No valid auth token"))
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = "[::1]:50051".parse().unwrap();
     let server = EchoServer::default();
 
-    Server::builder()
-        .interceptor_fn(move |svc, req| {
-            let auth_header = req.headers().get("authorization").clone();
-
-            let authed = if let Some(auth_header) = auth_header {
-                auth_header == "Bearer some-secret-token"
-            } else {
-                false
-            };
-
-            let fut = svc.call(req);
+    let svc = pb::server::EchoServer::with_interceptor(server, check_auth);
 
-            async move {
-                if authed {
-                    fut.await
-                } else {
-                    // Cancel the inner future since we never await it
-                    // the IO never gets registered.
-                    drop(fut);
-                    let res = http::Response::builder()
-                        .header("grpc-status", "16")
-                        .body(BoxBody::empty())
-                        .unwrap();
-                    Ok(res)
-                }
-            }
-        })
-        .add_service(pb::server::EchoServer::new(server))
-        .serve(addr)
-        .await?;
+    Server::builder().add_service(svc).serve(addr).await?;
 
     Ok(())
 }
@@ -1,8 +1,11 @@
 use crate::{
     body::{Body, BoxBody},
     client::GrpcService,
-    codec::{encode_client, Codec, Streaming},
-    Code, Request, Response, Status,
+    codec::{
+        encode_client, Codec, CompressionEncoding, EnabledCompressionEncodings, Streaming,
+        ACCEPT_ENCODING_HEADER, ENCODING_HEADER,
+    },
+    Code, Interceptor, Request, Response, Status,
 };
 use bytes::Bytes;
 use futures_core::Stream;
@@ -29,12 +32,74 @@ use std::fmt;
 /// [gRPC protocol definition]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#requests
 pub struct Grpc<T> {
     inner: T,
+    /// Which compression encoding to use for requests.
+    send_compression_encoding: Option<CompressionEncoding>,
+    /// Which compression encodings to accept for responses.
+    accept_compression_encodings: EnabledCompressionEncodings,
+    interceptor: Option<Interceptor>,
 }
 
+/// Marks the HTTP requests of unary calls, whose body is a single message.
+///
+/// Middleware like hedging relies on this to only buffer bodies that are
+/// known to end.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Unary;
+
 impl<T> Grpc<T> {
     /// Creates a new gRPC client with the provided [`GrpcService`].
     pub fn new(inner: T) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            send_compression_encoding: None,
+            accept_compression_encodings: EnabledCompressionEncodings::default(),
+            interceptor: None,
+        }
+    }
+
+    /// Creates a new gRPC client with the provided [`GrpcService`] that runs
+    /// every request through `interceptor` before sending it.
+    ///
+    /// A request the interceptor rejects is not sent, and the call fails with
+    /// the returned [`Status`].
+    pub fn with_interceptor(inner: T, interceptor: impl Into<Interceptor>) -> Self {
+        Self {
+            interceptor: Some(interceptor.into()),
+            ..Self::new(inner)
+        }
+    }
+
+    /// Compress requests with `gzip`.
+    ///
+    /// Requires the server to accept `gzip` otherwise it might respond with an
+    /// error.
+    pub fn send_gzip(self) -> Self {
+        self.send_compressed(CompressionEncoding::gzip())
+    }
+
+    /// Enable accepting `gzip` compressed responses.
+    ///
+    /// This advertises `gzip` through the `grpc-accept-encoding` header. The
+    /// server may still choose to respond uncompressed.
+    pub fn accept_gzip(self) -> Self {
+        self.accept_compressed(CompressionEncoding::gzip())
+    }
+
+    /// Compress requests with `encoding`.
+    ///
+    /// Requires the server to accept `encoding` otherwise it will respond with
+    /// `Code::Unimplemented`.
+    pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+        self.send_compression_encoding = Some(encoding);
+        self
+    }
+
+    /// Enable accepting responses compressed with `encoding`.
+    ///
+    /// Encodings are advertised to the server in the order they were enabled.
+    pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+        self.accept_compression_encodings.enable(encoding);
+        self
     }
 
     /// Check if the inner [`GrpcService`] is able to accept a  new request.
@@ -66,7 +131,7 @@ impl<T> Grpc<T> {
         M2: Send + Sync + 'static,
     {
         let request = request.map(|m| stream::once(future::ready(m)));
-        self.client_streaming(request, path, codec).await
+        self.send_client_streaming(request, path, codec, true).await
     }
 
     /// Send a client side streaming gRPC request.
@@ -86,7 +151,29 @@ impl<T> Grpc<T> {
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
     {
-        let (mut parts, body) = self.streaming(request, path, codec).await?.into_parts();
+        self.send_client_streaming(request, path, codec, false)
+            .await
+    }
+
+    async fn send_client_streaming<S, M1, M2, C>(
+        &mut self,
+        request: Request<S>,
+        path: PathAndQuery,
+        codec: C,
+        unary: bool,
+    ) -> Result<Response<M2>, Status>
+    where
+        T: GrpcService<BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        <T::ResponseBody as HttpBody>::Error: Into<crate::Error>,
+        <T::ResponseBody as HttpBody>::Data: Into<Bytes>,
+        S: Stream<Item = M1> + Send + Sync + 'static,
+        C: Codec<Encode = M1, Decode = M2>,
+        M1: Send + Sync + 'static,
+        M2: Send + Sync + 'static,
+    {
+        let (mut parts, body, extensions) =
+            self.send(request, path, codec, unary).await?.into_parts();
 
         futures_util::pin_mut!(body);
 
@@ -99,7 +186,7 @@ impl<T> Grpc<T> {
             parts.merge(trailers);
         }
 
-        Ok(Response::from_parts(parts, message))
+        Ok(Response::from_parts(parts, message, extensions))
     }
 
     /// Send a server side streaming gRPC request.
@@ -124,10 +211,30 @@ impl<T> Grpc<T> {
 
     /// Send a bi-directional streaming gRPC request.
     pub async fn streaming<S, M1, M2, C>(
+        &mut self,
+        request: Request<S>,
+        path: PathAndQuery,
+        codec: C,
+    ) -> Result<Response<Streaming<M2>>, Status>
+    where
+        T: GrpcService<BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        <T::ResponseBody as HttpBody>::Data: Into<Bytes>,
+        <T::ResponseBody as HttpBody>::Error: Into<crate::Error>,
+        S: Stream<Item = M1> + Send + Sync + 'static,
+        C: Codec<Encode = M1, Decode = M2>,
+        M1: Send + Sync + 'static,
+        M2: Send + Sync + 'static,
+    {
+        self.send(request, path, codec, false).await
+    }
+
+    async fn send<S, M1, M2, C>(
         &mut self,
         request: Request<S>,
         path: PathAndQuery,
         mut codec: C,
+        unary: bool,
     ) -> Result<Response<Streaming<M2>>, Status>
     where
         T: GrpcService<BoxBody>,
@@ -139,17 +246,36 @@ impl<T> Grpc<T> {
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
     {
+        let request = match &self.interceptor {
+            Some(interceptor) => request.intercept(interceptor)?,
+            None => request,
+        };
+
         let mut parts = Parts::default();
         parts.path_and_query = Some(path);
 
         let uri = Uri::from_parts(parts).expect("path_and_query only is valid Uri");
 
+        // Settings of the client take precedence over the ones of the
+        // transport it sends on.
+        let send_compression_encoding = self
+            .send_compression_encoding
+            .clone()
+            .or_else(|| self.inner.send_compression_encoding());
+        let accept_compression_encodings = self
+            .accept_compression_encodings
+            .merge(&self.inner.accept_compression_encodings());
+
         let request = request
-            .map(|s| encode_client(codec.encoder(), s))
+            .map(|s| encode_client(codec.encoder(), s, send_compression_encoding.clone()))
             .map(BoxBody::new);
 
         let mut request = request.into_http(uri);
 
+        if unary {
+            request.extensions_mut().insert(Unary);
+        }
+
         // Add the gRPC related HTTP headers
         request
             .headers_mut()
@@ -160,6 +286,18 @@ impl<T> Grpc<T> {
             .headers_mut()
             .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
 
+        if let Some(encoding) = send_compression_encoding {
+            request
+                .headers_mut()
+                .insert(ENCODING_HEADER, encoding.to_header_value());
+        }
+
+        if let Some(header_value) = accept_compression_encodings.to_accept_encoding_header_value() {
+            request
+                .headers_mut()
+                .insert(ACCEPT_ENCODING_HEADER, header_value);
+        }
+
         let response = self
             .inner
             .call(request)
@@ -167,6 +305,10 @@ impl<T> Grpc<T> {
             .map_err(|err| Status::from_error(&*(err.into())))?;
 
         let status_code = response.status();
+        let encoding = CompressionEncoding::from_encoding_header(
+            response.headers(),
+            &accept_compression_encodings,
+        )?;
         let trailers_only_status = Status::from_header_map(response.headers());
 
         // We do not need to check for trailers if the `grpc-status` header is present
@@ -181,9 +323,12 @@ impl<T> Grpc<T> {
             true
         };
 
+        let max_decompressed_size = self.inner.max_decompressed_message_size();
+
         let response = response.map(|body| {
             if expect_additional_trailers {
-                Streaming::new_response(codec.decoder(), body, status_code)
+                Streaming::new_response(codec.decoder(), body, status_code, encoding)
+                    .max_decompressed_size(max_decompressed_size)
             } else {
                 Streaming::new_empty(codec.decoder(), body)
             }
@@ -197,6 +342,9 @@ impl<T: Clone> Clone for Grpc<T> {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            send_compression_encoding: self.send_compression_encoding.clone(),
+            accept_compression_encodings: self.accept_compression_encodings.clone(),
+            interceptor: self.interceptor.clone(),
         }
     }
 }
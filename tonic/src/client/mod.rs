@@ -7,7 +7,7 @@
 //! This client is generally used by some code generation tool to provide stubs
 //! for the gRPC service. Thusly, they are a bit cumbersome to use by hand.
 
-mod grpc;
+pub(crate) mod grpc;
 mod service;
 
 pub use self::grpc::Grpc;
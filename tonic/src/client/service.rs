@@ -1,4 +1,7 @@
-use crate::body::Body;
+use crate::{
+    body::Body,
+    codec::{CompressionEncoding, EnabledCompressionEncodings},
+};
 use http_body::Body as HttpBody;
 use std::future::Future;
 use std::task::{Context, Poll};
@@ -28,6 +31,29 @@ pub trait GrpcService<ReqBody> {
     ///
     /// Reference [`Service::call`].
     fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future;
+
+    /// The encoding requests are compressed with, unless the client picks
+    /// one itself.
+    ///
+    /// This lets a transport like `Channel` configure compression for every
+    /// client built on top of it.
+    #[doc(hidden)]
+    fn send_compression_encoding(&self) -> Option<CompressionEncoding> {
+        None
+    }
+
+    /// Encodings accepted for responses in addition to the ones the client
+    /// enables itself.
+    #[doc(hidden)]
+    fn accept_compression_encodings(&self) -> EnabledCompressionEncodings {
+        EnabledCompressionEncodings::default()
+    }
+
+    /// The most a compressed response message may decompress to, if limited.
+    #[doc(hidden)]
+    fn max_decompressed_message_size(&self) -> Option<usize> {
+        None
+    }
 }
 
 impl<T, ReqBody, ResBody> GrpcService<ReqBody> for T
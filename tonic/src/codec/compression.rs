@@ -0,0 +1,422 @@
+use crate::{Code, Status};
+use bytes::BytesMut;
+use flate2::read::{GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder};
+use http::{HeaderMap, HeaderValue};
+use std::{
+    fmt,
+    io::{self, Read},
+    sync::Arc,
+};
+use tracing::trace;
+
+pub(crate) const ENCODING_HEADER: &str = "grpc-encoding";
+pub(crate) const ACCEPT_ENCODING_HEADER: &str = "grpc-accept-encoding";
+
+/// A message compression algorithm.
+///
+/// Implement this trait to make an algorithm that tonic does not ship with
+/// available to clients and servers. The algorithm is registered by wrapping
+/// it in a [`CompressionEncoding`] and enabling that encoding on the client or
+/// server.
+///
+/// ```rust,ignore
+/// use std::io;
+/// use tonic::codec::{CompressionEncoding, Compressor};
+///
+/// struct Zstd;
+///
+/// impl Compressor for Zstd {
+///     fn name(&self) -> &'static str {
+///         "zstd"
+///     }
+///
+///     fn compress(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<()> {
+///         zstd::stream::copy_encode(src, dst, 0)
+///     }
+///
+///     fn decompress(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<()> {
+///         zstd::stream::copy_decode(src, dst)
+///     }
+/// }
+///
+/// let zstd = CompressionEncoding::new(Zstd);
+/// ```
+pub trait Compressor: Send + Sync + 'static {
+    /// The name of the encoding as it appears in the `grpc-encoding` header.
+    fn name(&self) -> &'static str;
+
+    /// Compress `src`, appending the output to `dst`.
+    fn compress(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Decompress `src`, appending the output to `dst`.
+    fn decompress(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Decompress `src`, appending the output to `dst`, but stop once more
+    /// than `limit` bytes have been produced.
+    ///
+    /// Messages that decompress to more than `limit` bytes are rejected, so
+    /// the output past that point is never looked at. The default
+    /// implementation decompresses all of `src` with
+    /// [`decompress`](Compressor::decompress); override it to avoid
+    /// inflating oversized messages into memory.
+    fn decompress_limited(&self, src: &[u8], dst: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+        let _ = limit;
+        self.decompress(src, dst)
+    }
+}
+
+/// A compression encoding that can be used for gRPC messages.
+///
+/// The encoding is negotiated per call through the `grpc-encoding` and
+/// `grpc-accept-encoding` headers as described in the [gRPC compression spec].
+///
+/// [gRPC compression spec]: https://github.com/grpc/grpc/blob/master/doc/compression.md
+#[derive(Clone)]
+pub struct CompressionEncoding {
+    compressor: Arc<dyn Compressor>,
+}
+
+/// The set of compression encodings that have been enabled on a client or server.
+///
+/// This acts as the registry that incoming `grpc-encoding` and
+/// `grpc-accept-encoding` headers are matched against. The order in which
+/// encodings are enabled is the order in which they are advertised.
+#[derive(Clone, Debug, Default)]
+pub struct EnabledCompressionEncodings {
+    encodings: Vec<CompressionEncoding>,
+}
+
+/// Compression defaults that a transport attaches to each request as an
+/// extension so that every service behind it picks them up.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CompressionConfig {
+    pub(crate) accept: EnabledCompressionEncodings,
+    pub(crate) send: EnabledCompressionEncodings,
+    pub(crate) max_decompressed_size: Option<usize>,
+}
+
+impl EnabledCompressionEncodings {
+    /// Check if a [`CompressionEncoding`] is enabled.
+    pub fn is_enabled(&self, encoding: &CompressionEncoding) -> bool {
+        self.get(encoding.name().as_bytes()).is_some()
+    }
+
+    /// Enable a [`CompressionEncoding`].
+    ///
+    /// Enabling an encoding with the same name as an already enabled encoding
+    /// has no effect.
+    pub fn enable(&mut self, encoding: CompressionEncoding) {
+        if !self.is_enabled(&encoding) {
+            self.encodings.push(encoding);
+        }
+    }
+
+    pub(crate) fn get(&self, name: &[u8]) -> Option<&CompressionEncoding> {
+        self.encodings
+            .iter()
+            .find(|encoding| encoding.name().as_bytes() == name)
+    }
+
+    pub(crate) fn merge(&self, other: &Self) -> Self {
+        let mut merged = self.clone();
+        for encoding in &other.encodings {
+            merged.enable(encoding.clone());
+        }
+        merged
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        self.encodings
+            .iter()
+            .map(CompressionEncoding::name)
+            .chain(std::iter::once("identity"))
+            .collect()
+    }
+
+    pub(crate) fn to_accept_encoding_header_value(&self) -> Option<HeaderValue> {
+        if self.encodings.is_empty() {
+            return None;
+        }
+
+        HeaderValue::from_str(&self.names().join(",")).ok()
+    }
+}
+
+impl CompressionEncoding {
+    /// The `gzip` encoding.
+    pub fn gzip() -> Self {
+        Self::new(Gzip)
+    }
+
+    /// The `deflate` encoding, using the zlib format.
+    pub fn deflate() -> Self {
+        Self::new(Deflate)
+    }
+
+    /// Create an encoding from a custom [`Compressor`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name of the compressor is not a valid header value.
+    pub fn new<C: Compressor>(compressor: C) -> Self {
+        // Validate the name up front instead of on the first request.
+        let _ = HeaderValue::from_static(compressor.name());
+
+        Self {
+            compressor: Arc::new(compressor),
+        }
+    }
+
+    /// The name of this encoding as it appears in the `grpc-encoding` header.
+    pub fn name(&self) -> &'static str {
+        self.compressor.name()
+    }
+
+    /// Based on the `grpc-accept-encoding` header, pick an encoding to use.
+    ///
+    /// The peer's preference order is respected.
+    pub(crate) fn from_accept_encoding_header(
+        map: &HeaderMap,
+        enabled_encodings: &EnabledCompressionEncodings,
+    ) -> Option<Self> {
+        let header_value = map.get(ACCEPT_ENCODING_HEADER)?;
+        let header_value_str = header_value.to_str().ok()?;
+
+        header_value_str
+            .split(',')
+            .map(|value| value.trim())
+            .filter_map(|value| enabled_encodings.get(value.as_bytes()))
+            .next()
+            .cloned()
+    }
+
+    /// Get the value of the `grpc-encoding` header.
+    ///
+    /// Returns an error if the peer used an encoding that hasn't been enabled.
+    pub(crate) fn from_encoding_header(
+        map: &HeaderMap,
+        enabled_encodings: &EnabledCompressionEncodings,
+    ) -> Result<Option<Self>, Status> {
+        let header_value = if let Some(value) = map.get(ENCODING_HEADER) {
+            value
+        } else {
+            return Ok(None);
+        };
+
+        match header_value.as_bytes() {
+            b"identity" => Ok(None),
+            other => match enabled_encodings.get(other) {
+                Some(encoding) => Ok(Some(encoding.clone())),
+                None => {
+                    trace!("unsupported compression encoding");
+                    Err(Status::new(
+                        Code::Unimplemented,
+                        format!(
+                            "Content is compressed with `{}` which isn't supported. Supported encodings: {}",
+                            String::from_utf8_lossy(other),
+                            enabled_encodings.names().join(", "),
+                        ),
+                    ))
+                }
+            },
+        }
+    }
+
+    pub(crate) fn to_header_value(&self) -> HeaderValue {
+        HeaderValue::from_static(self.name())
+    }
+}
+
+impl PartialEq for CompressionEncoding {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for CompressionEncoding {}
+
+impl fmt::Debug for CompressionEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CompressionEncoding")
+            .field(&self.name())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Gzip;
+
+impl Compressor for Gzip {
+    fn name(&self) -> &'static str {
+        "gzip"
+    }
+
+    fn compress(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<()> {
+        // FIXME: support customizing the compression level
+        let mut encoder = GzEncoder::new(src, flate2::Compression::new(6));
+        encoder.read_to_end(dst).map(drop)
+    }
+
+    fn decompress(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<()> {
+        GzDecoder::new(src).read_to_end(dst).map(drop)
+    }
+
+    fn decompress_limited(&self, src: &[u8], dst: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+        GzDecoder::new(src)
+            .take(limit as u64 + 1)
+            .read_to_end(dst)
+            .map(drop)
+    }
+}
+
+#[derive(Debug)]
+struct Deflate;
+
+impl Compressor for Deflate {
+    fn name(&self) -> &'static str {
+        "deflate"
+    }
+
+    fn compress(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<()> {
+        let mut encoder = ZlibEncoder::new(src, flate2::Compression::new(6));
+        encoder.read_to_end(dst).map(drop)
+    }
+
+    fn decompress(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<()> {
+        ZlibDecoder::new(src).read_to_end(dst).map(drop)
+    }
+
+    fn decompress_limited(&self, src: &[u8], dst: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+        ZlibDecoder::new(src)
+            .take(limit as u64 + 1)
+            .read_to_end(dst)
+            .map(drop)
+    }
+}
+
+/// Compress `len` bytes from `decompressed_buf` into `out_buf`.
+pub(crate) fn compress(
+    encoding: &CompressionEncoding,
+    decompressed_buf: &mut BytesMut,
+    out_buf: &mut BytesMut,
+    len: usize,
+) -> Result<(), io::Error> {
+    let mut compressed = Vec::with_capacity(len);
+    encoding
+        .compressor
+        .compress(&decompressed_buf[0..len], &mut compressed)?;
+
+    out_buf.extend_from_slice(&compressed);
+    decompressed_buf.advance(len);
+
+    Ok(())
+}
+
+/// Decompress `len` bytes from `compressed_buf` into `out_buf`.
+///
+/// At most `limit + 1` bytes are written to `out_buf`, so that the caller can
+/// tell whether the message was larger than `limit`.
+pub(crate) fn decompress(
+    encoding: &CompressionEncoding,
+    compressed_buf: &mut BytesMut,
+    out_buf: &mut BytesMut,
+    len: usize,
+    limit: usize,
+) -> Result<(), io::Error> {
+    let mut decompressed = Vec::with_capacity(std::cmp::min(len * 2, limit));
+    encoding
+        .compressor
+        .decompress_limited(&compressed_buf[0..len], &mut decompressed, limit)?;
+    decompressed.truncate(limit + 1);
+
+    out_buf.extend_from_slice(&decompressed);
+    compressed_buf.advance(len);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled(encodings: Vec<CompressionEncoding>) -> EnabledCompressionEncodings {
+        let mut enabled = EnabledCompressionEncodings::default();
+        for encoding in encodings {
+            enabled.enable(encoding);
+        }
+        enabled
+    }
+
+    #[test]
+    fn accept_encoding_respects_peer_preference() {
+        let enabled = enabled(vec![
+            CompressionEncoding::gzip(),
+            CompressionEncoding::deflate(),
+        ]);
+
+        let mut map = HeaderMap::new();
+        map.insert(
+            ACCEPT_ENCODING_HEADER,
+            HeaderValue::from_static("zstd, deflate, gzip"),
+        );
+
+        let encoding = CompressionEncoding::from_accept_encoding_header(&map, &enabled);
+        assert_eq!(encoding, Some(CompressionEncoding::deflate()));
+    }
+
+    #[test]
+    fn unknown_encoding_is_unimplemented() {
+        let enabled = enabled(vec![CompressionEncoding::gzip()]);
+
+        let mut map = HeaderMap::new();
+        map.insert(ENCODING_HEADER, HeaderValue::from_static("zstd"));
+
+        let status = CompressionEncoding::from_encoding_header(&map, &enabled).unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented);
+        assert!(status.message().contains("gzip, identity"));
+
+        assert_eq!(
+            enabled.to_accept_encoding_header_value().unwrap(),
+            "gzip,identity"
+        );
+    }
+
+    #[test]
+    fn deflate_roundtrip() {
+        let encoding = CompressionEncoding::deflate();
+        let data = vec![7u8; 4096];
+
+        let mut src = BytesMut::from(&data[..]);
+        let mut compressed = BytesMut::new();
+        compress(&encoding, &mut src, &mut compressed, data.len()).unwrap();
+
+        let len = compressed.len();
+        let mut decompressed = BytesMut::new();
+        decompress(
+            &encoding,
+            &mut compressed,
+            &mut decompressed,
+            len,
+            data.len(),
+        )
+        .unwrap();
+
+        assert_eq!(&decompressed[..], &data[..]);
+    }
+
+    #[test]
+    fn gzip_decompression_stops_past_limit() {
+        let encoding = CompressionEncoding::gzip();
+        let data = vec![0u8; 1024 * 1024];
+
+        let mut src = BytesMut::from(&data[..]);
+        let mut compressed = BytesMut::new();
+        compress(&encoding, &mut src, &mut compressed, data.len()).unwrap();
+
+        let len = compressed.len();
+        let mut decompressed = BytesMut::new();
+        decompress(&encoding, &mut compressed, &mut decompressed, len, 1024).unwrap();
+
+        assert_eq!(decompressed.len(), 1025);
+    }
+}
@@ -1,4 +1,7 @@
-use super::Decoder;
+use super::{
+    compression::{decompress, CompressionEncoding},
+    Decoder,
+};
 use crate::{body::BoxBody, metadata::MetadataMap, Code, Status};
 use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
 use futures_core::Stream;
@@ -13,6 +16,12 @@ use std::{
 use tracing::{debug, trace};
 
 const BUFFER_SIZE: usize = 8 * 1024;
+const HEADER_SIZE: usize = 5;
+
+/// The largest a compressed message may grow to when decompressed, unless
+/// configured otherwise. This is as large as the length prefix of a message
+/// allows, so compressing a message never makes it too large to receive.
+pub(crate) const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = std::u32::MAX as usize;
 
 /// Streaming requests and responses.
 ///
@@ -25,6 +34,9 @@ pub struct Streaming<T> {
     direction: Direction,
     buf: BytesMut,
     trailers: Option<MetadataMap>,
+    encoding: Option<CompressionEncoding>,
+    decompress_buf: BytesMut,
+    max_decompressed_size: usize,
 }
 
 impl<T> Unpin for Streaming<T> {}
@@ -43,14 +55,19 @@ enum Direction {
 }
 
 impl<T> Streaming<T> {
-    pub(crate) fn new_response<B, D>(decoder: D, body: B, status_code: StatusCode) -> Self
+    pub(crate) fn new_response<B, D>(
+        decoder: D,
+        body: B,
+        status_code: StatusCode,
+        encoding: Option<CompressionEncoding>,
+    ) -> Self
     where
         B: Body + Send + Sync + 'static,
         B::Data: Into<Bytes>,
         B::Error: Into<crate::Error>,
         D: Decoder<Item = T, Error = Status> + Send + Sync + 'static,
     {
-        Self::new(decoder, body, Direction::Response(status_code))
+        Self::new(decoder, body, Direction::Response(status_code), encoding)
     }
 
     pub(crate) fn new_empty<B, D>(decoder: D, body: B) -> Self
@@ -60,20 +77,29 @@ impl<T> Streaming<T> {
         B::Error: Into<crate::Error>,
         D: Decoder<Item = T, Error = Status> + Send + Sync + 'static,
     {
-        Self::new(decoder, body, Direction::EmptyResponse)
+        Self::new(decoder, body, Direction::EmptyResponse, None)
     }
 
-    pub(crate) fn new_request<B, D>(decoder: D, body: B) -> Self
+    pub(crate) fn new_request<B, D>(
+        decoder: D,
+        body: B,
+        encoding: Option<CompressionEncoding>,
+    ) -> Self
     where
         B: Body + Send + Sync + 'static,
         B::Data: Into<Bytes>,
         B::Error: Into<crate::Error>,
         D: Decoder<Item = T, Error = Status> + Send + Sync + 'static,
     {
-        Self::new(decoder, body, Direction::Request)
+        Self::new(decoder, body, Direction::Request, encoding)
     }
 
-    fn new<B, D>(decoder: D, body: B, direction: Direction) -> Self
+    fn new<B, D>(
+        decoder: D,
+        body: B,
+        direction: Direction,
+        encoding: Option<CompressionEncoding>,
+    ) -> Self
     where
         B: Body + Send + Sync + 'static,
         B::Data: Into<Bytes>,
@@ -87,6 +113,18 @@ impl<T> Streaming<T> {
             direction,
             buf: BytesMut::with_capacity(BUFFER_SIZE),
             trailers: None,
+            encoding,
+            decompress_buf: BytesMut::new(),
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Reject compressed messages that decompress to more than `limit`
+    /// bytes, with `Code::ResourceExhausted`.
+    pub(crate) fn max_decompressed_size(self, limit: Option<usize>) -> Self {
+        Self {
+            max_decompressed_size: limit.unwrap_or(DEFAULT_MAX_DECOMPRESSED_SIZE),
+            ..self
         }
     }
 }
@@ -157,18 +195,25 @@ impl<T> Streaming<T> {
         let mut buf = (&self.buf[..]).into_buf();
 
         if let State::ReadHeader = self.state {
-            if buf.remaining() < 5 {
+            if buf.remaining() < HEADER_SIZE {
                 return Ok(None);
             }
 
             let is_compressed = match buf.get_u8() {
                 0 => false,
                 1 => {
-                    trace!("message compressed, compression not supported yet");
-                    return Err(Status::new(
-                        Code::Unimplemented,
-                        "Message compressed, compression not supported yet.".to_string(),
-                    ));
+                    if self.encoding.is_some() {
+                        true
+                    } else {
+                        // A message with the compressed flag set but without a
+                        // `grpc-encoding` other than identity MUST fail with
+                        // INTERNAL as per the gRPC compression spec.
+                        trace!("message compressed, but no grpc-encoding was specified");
+                        return Err(Status::new(
+                            Code::Internal,
+                            "Message compressed, but no grpc-encoding was specified.".to_string(),
+                        ));
+                    }
                 }
                 f => {
                     trace!("unexpected compression flag");
@@ -186,17 +231,48 @@ impl<T> Streaming<T> {
             }
         }
 
-        if let State::ReadBody { len, .. } = &self.state {
+        if let State::ReadBody { compression, len } = &self.state {
             // if we haven't read enough of the message then return and keep
             // reading
-            if buf.remaining() < *len || self.buf.len() < *len + 5 {
+            if buf.remaining() < *len || self.buf.len() < *len + HEADER_SIZE {
                 return Ok(None);
             }
 
             // advance past the header
-            self.buf.advance(5);
+            self.buf.advance(HEADER_SIZE);
+
+            let decoding_result = match (*compression, &self.encoding) {
+                (true, Some(encoding)) => {
+                    self.decompress_buf.clear();
+
+                    if let Err(err) = decompress(
+                        encoding,
+                        &mut self.buf,
+                        &mut self.decompress_buf,
+                        *len,
+                        self.max_decompressed_size,
+                    ) {
+                        let message = format!("Error decompressing: {}", err);
+                        return Err(Status::new(Code::Internal, message));
+                    }
+
+                    if self.decompress_buf.len() > self.max_decompressed_size {
+                        trace!("decompressed message exceeds the maximum size");
+                        return Err(Status::new(
+                            Code::ResourceExhausted,
+                            format!(
+                                "Message decompresses to more than the maximum of {} bytes.",
+                                self.max_decompressed_size
+                            ),
+                        ));
+                    }
+
+                    self.decoder.decode(&mut self.decompress_buf)
+                }
+                _ => self.decoder.decode(&mut self.buf),
+            };
 
-            match self.decoder.decode(&mut self.buf) {
+            match decoding_result {
                 Ok(Some(msg)) => {
                     self.state = State::ReadHeader;
                     return Ok(Some(msg));
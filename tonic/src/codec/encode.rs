@@ -1,3 +1,4 @@
+use super::compression::{compress, CompressionEncoding};
 use crate::{body::BytesBuf, Code, Status};
 use bytes::{BufMut, BytesMut, IntoBuf};
 use futures_core::{Stream, TryStream};
@@ -10,61 +11,95 @@ use std::task::{Context, Poll};
 use tokio_codec::Encoder;
 
 const BUFFER_SIZE: usize = 8 * 1024;
+const HEADER_SIZE: usize = 5;
 
 pub(crate) fn encode_server<T, U>(
     encoder: T,
     source: U,
+    compression_encoding: Option<CompressionEncoding>,
 ) -> EncodeBody<impl Stream<Item = Result<BytesBuf, Status>>>
 where
     T: Encoder<Error = Status> + Send + Sync + 'static,
     T::Item: Send + Sync,
     U: Stream<Item = Result<T::Item, Status>> + Send + Sync + 'static,
 {
-    let stream = encode(encoder, source).into_stream();
+    let stream = encode(encoder, source, compression_encoding).into_stream();
     EncodeBody::new_server(stream)
 }
 
 pub(crate) fn encode_client<T, U>(
     encoder: T,
     source: U,
+    compression_encoding: Option<CompressionEncoding>,
 ) -> EncodeBody<impl Stream<Item = Result<BytesBuf, Status>>>
 where
     T: Encoder<Error = Status> + Send + Sync + 'static,
     T::Item: Send + Sync,
     U: Stream<Item = T::Item> + Send + Sync + 'static,
 {
-    let stream = encode(encoder, source.map(|x| Ok(x))).into_stream();
+    let stream = encode(encoder, source.map(|x| Ok(x)), compression_encoding).into_stream();
     EncodeBody::new_client(stream)
 }
 
-fn encode<T, U>(mut encoder: T, source: U) -> impl TryStream<Ok = BytesBuf, Error = Status>
+fn encode<T, U>(
+    mut encoder: T,
+    source: U,
+    compression_encoding: Option<CompressionEncoding>,
+) -> impl TryStream<Ok = BytesBuf, Error = Status>
 where
     T: Encoder<Error = Status>,
     U: Stream<Item = Result<T::Item, Status>>,
 {
     async_stream::stream! {
         let mut buf = BytesMut::with_capacity(BUFFER_SIZE);
+        let mut uncompression_buf = if compression_encoding.is_some() {
+            BytesMut::with_capacity(BUFFER_SIZE)
+        } else {
+            BytesMut::new()
+        };
         futures_util::pin_mut!(source);
 
         loop {
             match source.next().await {
                 Some(Ok(item)) => {
-                    buf.reserve(5);
+                    buf.reserve(HEADER_SIZE);
                     unsafe {
-                        buf.advance_mut(5);
+                        buf.advance_mut(HEADER_SIZE);
+                    }
+
+                    if let Some(encoding) = &compression_encoding {
+                        uncompression_buf.clear();
+
+                        encoder.encode(item, &mut uncompression_buf).map_err(drop).unwrap();
+
+                        let uncompressed_len = uncompression_buf.len();
+
+                        if let Err(err) = compress(
+                            encoding,
+                            &mut uncompression_buf,
+                            &mut buf,
+                            uncompressed_len,
+                        ) {
+                            buf.clear();
+                            let message = format!("Error compressing: {}", err);
+                            yield Err(Status::new(Code::Internal, message));
+                            continue;
+                        }
+                    } else {
+                        encoder.encode(item, &mut buf).map_err(drop).unwrap();
                     }
-                    encoder.encode(item, &mut buf).map_err(drop).unwrap();
 
                     // now that we know length, we can write the header
-                    let len = buf.len() - 5;
+                    let len = buf.len() - HEADER_SIZE;
                     assert!(len <= std::u32::MAX as usize);
                     {
-                        let mut cursor = std::io::Cursor::new(&mut buf[..5]);
-                        cursor.put_u8(0); // byte must be 0, reserve doesn't auto-zero
+                        let mut cursor = std::io::Cursor::new(&mut buf[..HEADER_SIZE]);
+                        // byte must be set explicitly, reserve doesn't auto-zero
+                        cursor.put_u8(compression_encoding.is_some() as u8);
                         cursor.put_u32_be(len as u32);
                     }
 
-                    yield Ok(buf.split_to(len + 5).freeze().into_buf());
+                    yield Ok(buf.split_to(len + HEADER_SIZE).freeze().into_buf());
                 },
                 Some(Err(status)) => yield Err(status),
                 None => break,
@@ -1,8 +1,10 @@
 //! Generic encoding and decoding.
 //!
 //! This module contains the generic `Codec` trait and a protobuf codec
-//! based on prost.
+//! based on prost. It also contains the per-message compression support
+//! negotiated through the `grpc-encoding` and `grpc-accept-encoding` headers.
 
+mod compression;
 mod decode;
 mod encode;
 #[cfg(feature = "prost")]
@@ -11,6 +13,8 @@ mod prost;
 #[cfg(test)]
 mod tests;
 
+pub(crate) use self::compression::{CompressionConfig, ACCEPT_ENCODING_HEADER, ENCODING_HEADER};
+pub use self::compression::{CompressionEncoding, Compressor, EnabledCompressionEncodings};
 pub use self::decode::Streaming;
 pub(crate) use self::encode::{encode_client, encode_server};
 #[cfg(feature = "prost")]
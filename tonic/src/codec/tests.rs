@@ -1,9 +1,9 @@
 use super::{
     encode_server,
     prost::{ProstDecoder, ProstEncoder},
-    Streaming,
+    CompressionEncoding, Streaming,
 };
-use crate::Status;
+use crate::{Code, Status};
 use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
 use http_body::Body;
 use prost::Message;
@@ -41,7 +41,7 @@ async fn decode() {
         count: 0,
     };
 
-    let mut stream = Streaming::new_request(decoder, body);
+    let mut stream = Streaming::new_request(decoder, body, None);
 
     let mut i = 0usize;
     while let Some(msg) = stream.message().await.unwrap() {
@@ -61,7 +61,7 @@ async fn encode() {
     let messages = std::iter::repeat(Ok::<_, Status>(msg)).take(10000);
     let source = futures_util::stream::iter(messages);
 
-    let body = encode_server(encoder, source);
+    let body = encode_server(encoder, source, None);
 
     futures_util::pin_mut!(body);
 
@@ -70,6 +70,145 @@ async fn encode() {
     }
 }
 
+#[tokio::test]
+async fn compression_roundtrip() {
+    let encoder = ProstEncoder::<Msg>::default();
+    let decoder = ProstDecoder::<Msg>::default();
+
+    let data = vec![42u8; 10000];
+    let msg = Msg { data };
+
+    let messages = std::iter::repeat(Ok::<_, Status>(msg.clone())).take(3);
+    let source = futures_util::stream::iter(messages);
+
+    let body = encode_server(encoder, source, Some(CompressionEncoding::gzip()));
+
+    futures_util::pin_mut!(body);
+
+    let mut buf = BytesMut::new();
+    while let Some(r) = body.next().await {
+        let data = r.unwrap();
+        // compressed flag must be set on every message
+        assert_eq!(data.bytes()[0], 1);
+        buf.extend_from_slice(data.bytes());
+    }
+
+    // the repeated payload should compress well
+    assert!(buf.len() < msg.data.len());
+
+    let body = MockBody {
+        data: buf.freeze(),
+        partial_len: 100,
+        count: 0,
+    };
+
+    let mut stream = Streaming::new_request(decoder, body, Some(CompressionEncoding::gzip()));
+
+    let mut i = 0usize;
+    while let Some(decoded) = stream.message().await.unwrap() {
+        assert_eq!(decoded, msg);
+        i += 1;
+    }
+    assert_eq!(i, 3);
+}
+
+#[tokio::test]
+async fn decode_compressed_without_encoding() {
+    let encoder = ProstEncoder::<Msg>::default();
+    let decoder = ProstDecoder::<Msg>::default();
+
+    let msg = Msg {
+        data: vec![0u8; 100],
+    };
+    let source = futures_util::stream::iter(vec![Ok::<_, Status>(msg)]);
+
+    let body = encode_server(encoder, source, Some(CompressionEncoding::gzip()));
+
+    futures_util::pin_mut!(body);
+
+    let mut buf = BytesMut::new();
+    while let Some(r) = body.next().await {
+        buf.extend_from_slice(r.unwrap().bytes());
+    }
+
+    let data_len = buf.len();
+    let body = MockBody {
+        data: buf.freeze(),
+        partial_len: data_len,
+        count: 0,
+    };
+
+    let mut stream = Streaming::new_request(decoder, body, None);
+
+    let status = stream.message().await.unwrap_err();
+    assert_eq!(status.code(), Code::Internal);
+}
+
+#[tokio::test]
+async fn decode_large_uncompressed() {
+    let decoder = ProstDecoder::<Msg>::default();
+
+    let msg = Msg {
+        data: vec![1u8; 8 * 1024 * 1024],
+    };
+
+    let mut buf = BytesMut::new();
+    let len = msg.encoded_len();
+
+    buf.reserve(len + 5);
+    buf.put_u8(0);
+    buf.put_u32_be(len as u32);
+    msg.encode(&mut buf).unwrap();
+
+    let body = MockBody {
+        data: buf.freeze(),
+        partial_len: 64 * 1024,
+        count: 0,
+    };
+
+    // The decompression limit does not apply to uncompressed messages.
+    let mut stream = Streaming::new_request(decoder, body, None).max_decompressed_size(Some(1024));
+
+    assert_eq!(stream.message().await.unwrap(), Some(msg));
+}
+
+#[tokio::test]
+async fn decode_compressed_over_max_decompressed_size() {
+    let encoder = ProstEncoder::<Msg>::default();
+    let decoder = ProstDecoder::<Msg>::default();
+
+    let limit = 1024 * 1024;
+
+    // Zeros compress to a tiny fraction of the limit.
+    let msg = Msg {
+        data: vec![0u8; limit],
+    };
+    let source = futures_util::stream::iter(vec![Ok::<_, Status>(msg)]);
+
+    let body = encode_server(encoder, source, Some(CompressionEncoding::gzip()));
+
+    futures_util::pin_mut!(body);
+
+    let mut buf = BytesMut::new();
+    while let Some(r) = body.next().await {
+        buf.extend_from_slice(r.unwrap().bytes());
+    }
+    assert!(buf.len() < limit);
+
+    let data_len = buf.len();
+    let body = MockBody {
+        data: buf.freeze(),
+        partial_len: data_len,
+        count: 0,
+    };
+
+    let mut stream = Streaming::new_request(decoder, body, Some(CompressionEncoding::gzip()))
+        .max_decompressed_size(Some(limit));
+
+    let status = stream.message().await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+}
+
 #[derive(Debug)]
 struct MockBody {
     data: Bytes,
@@ -0,0 +1,394 @@
+//! Rich error details for [`Status`].
+//!
+//! The gRPC [error model] carries machine readable details alongside the
+//! status code and message. They are sent as an encoded `google.rpc.Status`
+//! message in the `grpc-status-details-bin` trailer, holding any number of
+//! detail messages packed as `google.protobuf.Any`.
+//!
+//! This module provides the standard detail messages from
+//! `google/rpc/error_details.proto` and helpers on [`Status`] to pack and
+//! unpack them.
+//!
+//! ```
+//! use tonic::{Code, Status};
+//! use tonic::error_details::{Any, BadRequest, FieldViolation};
+//!
+//! let bad_request = BadRequest {
+//!     field_violations: vec![FieldViolation {
+//!         field: "name".into(),
+//!         description: "must not be empty".into(),
+//!     }],
+//! };
+//!
+//! let status = Status::with_error_details(
+//!     Code::InvalidArgument,
+//!     "invalid request",
+//!     vec![Any::pack(&bad_request)],
+//! );
+//!
+//! let found = status.get_error_detail::<BadRequest>().unwrap();
+//! assert_eq!(found, bad_request);
+//! ```
+//!
+//! [`Status`]: ../struct.Status.html
+//! [error model]: https://cloud.google.com/apis/design/errors#error_model
+
+use crate::{Code, Status};
+use bytes::Bytes;
+use prost::{DecodeError, Message};
+use std::{collections::HashMap, convert::TryFrom};
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/";
+
+/// A message that can be used as an error detail.
+pub trait ErrorDetail: Message + Default {
+    /// The fully qualified protobuf name of the message.
+    const TYPE_NAME: &'static str;
+
+    /// The type URL the message is packed into an [`Any`] with.
+    fn type_url() -> String {
+        format!("{}{}", TYPE_URL_PREFIX, Self::TYPE_NAME)
+    }
+}
+
+/// `google.protobuf.Any`, an encoded message along with its type.
+#[derive(Clone, PartialEq, Message)]
+pub struct Any {
+    /// Identifies the type of the encoded message.
+    #[prost(string, tag = "1")]
+    pub type_url: String,
+    /// The encoded message.
+    #[prost(bytes, tag = "2")]
+    pub value: Vec<u8>,
+}
+
+impl Any {
+    /// Pack an error detail.
+    pub fn pack<M: ErrorDetail>(message: &M) -> Any {
+        let mut value = Vec::with_capacity(message.encoded_len());
+        message
+            .encode(&mut value)
+            .expect("Vec<u8> provides enough capacity");
+
+        Any {
+            type_url: M::type_url(),
+            value,
+        }
+    }
+
+    /// Unpack an error detail of type `M`.
+    ///
+    /// Returns `Ok(None)` if this holds a message of a different type.
+    pub fn unpack<M: ErrorDetail>(&self) -> Result<Option<M>, DecodeError> {
+        if self.type_url != M::type_url() {
+            return Ok(None);
+        }
+
+        M::decode(&self.value[..]).map(Some)
+    }
+}
+
+/// `google.rpc.Status`, the payload of the `grpc-status-details-bin` trailer.
+#[derive(Clone, PartialEq, Message)]
+pub struct RpcStatus {
+    /// The status code, which should be the same as the `grpc-status` code.
+    #[prost(int32, tag = "1")]
+    pub code: i32,
+    /// A developer-facing error message.
+    #[prost(string, tag = "2")]
+    pub message: String,
+    /// The error details.
+    #[prost(message, repeated, tag = "3")]
+    pub details: Vec<Any>,
+}
+
+/// `google.protobuf.Duration`, a signed span of time.
+#[derive(Clone, PartialEq, Message)]
+pub struct Duration {
+    /// Whole seconds of the span.
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    /// Fractions of a second at nanosecond resolution.
+    #[prost(int32, tag = "2")]
+    pub nanos: i32,
+}
+
+impl From<std::time::Duration> for Duration {
+    fn from(duration: std::time::Duration) -> Self {
+        Duration {
+            seconds: duration.as_secs() as i64,
+            nanos: duration.subsec_nanos() as i32,
+        }
+    }
+}
+
+impl TryFrom<Duration> for std::time::Duration {
+    type Error = Duration;
+
+    /// Fails for negative durations.
+    fn try_from(duration: Duration) -> Result<Self, Self::Error> {
+        if duration.seconds < 0 || duration.nanos < 0 {
+            return Err(duration);
+        }
+
+        Ok(std::time::Duration::new(
+            duration.seconds as u64,
+            duration.nanos as u32,
+        ))
+    }
+}
+
+/// Describes the cause of the error with structured details.
+#[derive(Clone, PartialEq, Message)]
+pub struct ErrorInfo {
+    /// The reason of the error, in `UPPER_SNAKE_CASE`.
+    #[prost(string, tag = "1")]
+    pub reason: String,
+    /// The logical grouping to which the reason belongs.
+    #[prost(string, tag = "2")]
+    pub domain: String,
+    /// Additional structured details about the error.
+    #[prost(map = "string, string", tag = "3")]
+    pub metadata: HashMap<String, String>,
+}
+
+/// Describes when the client may retry a failed request.
+#[derive(Clone, PartialEq, Message)]
+pub struct RetryInfo {
+    /// How long the client should wait before retrying.
+    #[prost(message, optional, tag = "1")]
+    pub retry_delay: Option<Duration>,
+}
+
+/// Describes additional debugging info.
+#[derive(Clone, PartialEq, Message)]
+pub struct DebugInfo {
+    /// The stack trace entries indicating where the error occurred.
+    #[prost(string, repeated, tag = "1")]
+    pub stack_entries: Vec<String>,
+    /// Additional debugging information provided by the server.
+    #[prost(string, tag = "2")]
+    pub detail: String,
+}
+
+/// Describes how a quota check failed.
+#[derive(Clone, PartialEq, Message)]
+pub struct QuotaFailure {
+    /// Describes all quota violations.
+    #[prost(message, repeated, tag = "1")]
+    pub violations: Vec<QuotaViolation>,
+}
+
+/// A single quota violation, see [`QuotaFailure`].
+#[derive(Clone, PartialEq, Message)]
+pub struct QuotaViolation {
+    /// The subject on which the quota check failed.
+    #[prost(string, tag = "1")]
+    pub subject: String,
+    /// A description of how the quota check failed.
+    #[prost(string, tag = "2")]
+    pub description: String,
+}
+
+/// Describes what preconditions have failed.
+#[derive(Clone, PartialEq, Message)]
+pub struct PreconditionFailure {
+    /// Describes all precondition violations.
+    #[prost(message, repeated, tag = "1")]
+    pub violations: Vec<PreconditionViolation>,
+}
+
+/// A single precondition violation, see [`PreconditionFailure`].
+#[derive(Clone, PartialEq, Message)]
+pub struct PreconditionViolation {
+    /// The type of precondition failure.
+    #[prost(string, tag = "1")]
+    pub r#type: String,
+    /// The subject, relative to the type, that failed.
+    #[prost(string, tag = "2")]
+    pub subject: String,
+    /// A description of how the precondition failed.
+    #[prost(string, tag = "3")]
+    pub description: String,
+}
+
+/// Describes violations in a client request.
+#[derive(Clone, PartialEq, Message)]
+pub struct BadRequest {
+    /// Describes all violations in a client request.
+    #[prost(message, repeated, tag = "1")]
+    pub field_violations: Vec<FieldViolation>,
+}
+
+/// A single bad request field, see [`BadRequest`].
+#[derive(Clone, PartialEq, Message)]
+pub struct FieldViolation {
+    /// A path leading to a field in the request body.
+    #[prost(string, tag = "1")]
+    pub field: String,
+    /// A description of why the request element is bad.
+    #[prost(string, tag = "2")]
+    pub description: String,
+}
+
+/// Contains metadata about the request that clients can attach when filing a
+/// bug or providing other forms of feedback.
+#[derive(Clone, PartialEq, Message)]
+pub struct RequestInfo {
+    /// An opaque string that should only be interpreted by the service that
+    /// generated it.
+    #[prost(string, tag = "1")]
+    pub request_id: String,
+    /// Any data that was used to serve this request.
+    #[prost(string, tag = "2")]
+    pub serving_data: String,
+}
+
+/// Describes the resource that is being accessed.
+#[derive(Clone, PartialEq, Message)]
+pub struct ResourceInfo {
+    /// The type of resource being accessed.
+    #[prost(string, tag = "1")]
+    pub resource_type: String,
+    /// The name of the resource being accessed.
+    #[prost(string, tag = "2")]
+    pub resource_name: String,
+    /// The owner of the resource.
+    #[prost(string, tag = "3")]
+    pub owner: String,
+    /// Describes what error is encountered when accessing this resource.
+    #[prost(string, tag = "4")]
+    pub description: String,
+}
+
+/// Provides links to documentation or for performing an out of band action.
+#[derive(Clone, PartialEq, Message)]
+pub struct Help {
+    /// URLs pointing to additional information on handling the current error.
+    #[prost(message, repeated, tag = "1")]
+    pub links: Vec<Link>,
+}
+
+/// A single link, see [`Help`].
+#[derive(Clone, PartialEq, Message)]
+pub struct Link {
+    /// Describes what the link offers.
+    #[prost(string, tag = "1")]
+    pub description: String,
+    /// The URL of the link.
+    #[prost(string, tag = "2")]
+    pub url: String,
+}
+
+/// Provides a localized error message that is safe to return to the user.
+#[derive(Clone, PartialEq, Message)]
+pub struct LocalizedMessage {
+    /// The locale used following the specification defined in BCP 47.
+    #[prost(string, tag = "1")]
+    pub locale: String,
+    /// The localized error message in the above locale.
+    #[prost(string, tag = "2")]
+    pub message: String,
+}
+
+macro_rules! error_detail {
+    ($($ty:ident => $name:expr,)*) => {
+        $(
+            impl ErrorDetail for $ty {
+                const TYPE_NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+error_detail! {
+    ErrorInfo => "google.rpc.ErrorInfo",
+    RetryInfo => "google.rpc.RetryInfo",
+    DebugInfo => "google.rpc.DebugInfo",
+    QuotaFailure => "google.rpc.QuotaFailure",
+    PreconditionFailure => "google.rpc.PreconditionFailure",
+    BadRequest => "google.rpc.BadRequest",
+    RequestInfo => "google.rpc.RequestInfo",
+    ResourceInfo => "google.rpc.ResourceInfo",
+    Help => "google.rpc.Help",
+    LocalizedMessage => "google.rpc.LocalizedMessage",
+}
+
+impl Status {
+    /// Create a new `Status` carrying the provided error details.
+    ///
+    /// The details are encoded as a `google.rpc.Status` message.
+    pub fn with_error_details(code: Code, message: impl Into<String>, details: Vec<Any>) -> Status {
+        let message = message.into();
+
+        let status = RpcStatus {
+            code: code as i32,
+            message: message.clone(),
+            details,
+        };
+
+        let mut buf = Vec::with_capacity(status.encoded_len());
+        status
+            .encode(&mut buf)
+            .expect("Vec<u8> provides enough capacity");
+
+        Status::with_details(code, message, Bytes::from(buf))
+    }
+
+    /// Decode the error details of this `Status`.
+    ///
+    /// Returns an empty list if the status has no details.
+    pub fn error_details(&self) -> Result<Vec<Any>, DecodeError> {
+        if self.details().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        RpcStatus::decode(self.details()).map(|status| status.details)
+    }
+
+    /// Get the first error detail of type `M`, if any.
+    ///
+    /// Details that fail to decode are skipped.
+    pub fn get_error_detail<M: ErrorDetail>(&self) -> Option<M> {
+        self.error_details()
+            .ok()?
+            .iter()
+            .filter_map(|any| any.unpack::<M>().ok())
+            .flatten()
+            .next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_details_roundtrip() {
+        let error_info = ErrorInfo {
+            reason: "STOCKOUT".into(),
+            domain: "example.com".into(),
+            metadata: vec![("sku".to_string(), "1234".to_string())]
+                .into_iter()
+                .collect(),
+        };
+        let retry_info = RetryInfo {
+            retry_delay: Some(std::time::Duration::from_millis(1500).into()),
+        };
+
+        let status = Status::with_error_details(
+            Code::Unavailable,
+            "out of stock",
+            vec![Any::pack(&error_info), Any::pack(&retry_info)],
+        );
+
+        let header_map = status.to_header_map().unwrap();
+        let status = Status::from_header_map(&header_map).unwrap();
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(status.error_details().unwrap().len(), 2);
+        assert_eq!(status.get_error_detail::<ErrorInfo>(), Some(error_info));
+        assert_eq!(status.get_error_detail::<RetryInfo>(), Some(retry_info));
+        assert_eq!(status.get_error_detail::<BadRequest>(), None);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{Request, Status};
+use std::{fmt, sync::Arc};
+
+type InterceptorFn = dyn Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static;
+
+/// Inspect and modify the metadata and extensions of calls, or reject them.
+///
+/// An interceptor runs before the message of a call is sent by a client, or
+/// before a server hands a call to its handler. It sees the call as a
+/// `Request<()>` and either returns it, possibly with changed metadata or
+/// extensions, or a [`Status`] to fail the call with. On the server that
+/// status is sent to the client as a trailers-only response.
+///
+/// Interceptors can be attached to a generated client or server with its
+/// `with_interceptor` constructor, and to every service of a server with
+/// [`Server::interceptor`].
+///
+/// ```
+/// # use tonic::{Code, Interceptor, Request, Status};
+/// let interceptor = Interceptor::new(|request: Request<()>| {
+///     let authorized = match request.metadata().get("authorization") {
+///         Some(token) => *token == "Bearer some-secret-token",
+///         None => false,
+///     };
+///
+///     if authorized {
+///         Ok(request)
+///     } else {
+///         Err(Status::new(Code::Unauthenticated, "No valid auth token"))
+///     }
+/// });
+/// ```
+///
+/// [`Status`]: struct.Status.html
+/// [`Server::interceptor`]: transport/struct.Server.html#method.interceptor
+#[derive(Clone)]
+pub struct Interceptor {
+    f: Arc<InterceptorFn>,
+}
+
+impl Interceptor {
+    /// Create a new interceptor from a function.
+    pub fn new(
+        f: impl Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static,
+    ) -> Self {
+        Interceptor { f: Arc::new(f) }
+    }
+
+    pub(crate) fn call(&self, request: Request<()>) -> Result<Request<()>, Status> {
+        (self.f)(request)
+    }
+}
+
+impl<F> From<F> for Interceptor
+where
+    F: Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static,
+{
+    fn from(f: F) -> Self {
+        Interceptor::new(f)
+    }
+}
+
+impl fmt::Debug for Interceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interceptor").finish()
+    }
+}
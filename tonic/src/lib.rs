@@ -30,7 +30,10 @@
 //! - `rustls-roots`: Adds system trust roots to `rustls`-based gRPC clients using the
 //! `rustls-native-certs` crate. Not enabled by default. `rustls` must be enabled to use
 //! `rustls-roots`.
-//! - `prost`: Enables the [`prost`] based gRPC [`Codec`] implementation.
+//! - `keepalive`: Enables HTTP/2 keepalive pings for the `transport` feature. Not enabled
+//! by default.
+//! - `prost`: Enables the [`prost`] based gRPC [`Codec`] implementation and the
+//! rich error model in [`error_details`].
 //!
 //! # Structure
 //!
@@ -60,6 +63,7 @@
 //! [`tonic-build`]: https://docs.rs/tonic-build
 //! [`tonic-examples`]: https://github.com/hyperium/tonic/tree/master/tonic-examples
 //! [`Codec`]: codec/trait.Codec.html
+//! [`error_details`]: error_details/index.html
 //! [`Channel`]: transport/struct.Channel.html
 //! [`Server`]: transport/struct.Server.html
 //! [`rustls`]: https://docs.rs/rustls
@@ -85,6 +89,9 @@
 pub mod body;
 pub mod client;
 pub mod codec;
+#[cfg(feature = "prost")]
+#[cfg_attr(docsrs, doc(cfg(feature = "prost")))]
+pub mod error_details;
 pub mod metadata;
 pub mod server;
 
@@ -92,6 +99,7 @@ pub mod server;
 #[cfg_attr(docsrs, doc(cfg(feature = "transport")))]
 pub mod transport;
 
+mod interceptor;
 mod macros;
 mod request;
 mod response;
@@ -104,6 +112,7 @@ pub use async_trait::async_trait;
 
 #[doc(inline)]
 pub use codec::Streaming;
+pub use interceptor::Interceptor;
 pub use request::{IntoRequest, IntoStreamingRequest, Request};
 pub use response::Response;
 pub use status::{Code, Status};
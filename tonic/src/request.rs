@@ -1,11 +1,26 @@
-use crate::metadata::MetadataMap;
+use crate::metadata::{MetadataMap, MetadataValue};
+#[cfg(feature = "transport")]
+use crate::transport::server::ConnectInfo;
+#[cfg(feature = "tls")]
+use crate::transport::Certificate;
+use crate::{Interceptor, Status};
 use futures_core::Stream;
+use http::{Extensions, HeaderMap};
+#[cfg(feature = "transport")]
+use std::net::SocketAddr;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub(crate) const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
 
 /// A gRPC request and metadata from an RPC call.
 #[derive(Debug)]
 pub struct Request<T> {
     metadata: MetadataMap,
     message: T,
+    deadline: Option<Instant>,
+    extensions: Extensions,
 }
 
 /// Trait implemented by RPC request types.
@@ -102,6 +117,8 @@ impl<T> Request<T> {
         Request {
             metadata: MetadataMap::new(),
             message,
+            deadline: None,
+            extensions: Extensions::new(),
         }
     }
 
@@ -130,10 +147,111 @@ impl<T> Request<T> {
         self.message
     }
 
+    /// Get a reference to the request extensions.
+    ///
+    /// Extensions carry typed values alongside the request that are never
+    /// sent over the wire, such as values attached by tower layers for the
+    /// handler to read. On the server they are the extensions of the
+    /// underlying `http::Request`, and on the client they are handed to
+    /// the `http::Request` that is sent.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Get a mutable reference to the request extensions.
+    ///
+    /// ```rust
+    /// # use tonic::Request;
+    /// #[derive(Debug, PartialEq)]
+    /// struct TenantId(u64);
+    ///
+    /// let mut request = Request::new(());
+    /// request.extensions_mut().insert(TenantId(7));
+    ///
+    /// assert_eq!(request.extensions().get::<TenantId>(), Some(&TenantId(7)));
+    /// ```
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Set the max duration the request is allowed to take.
+    ///
+    /// The duration is sent to the server in the `grpc-timeout` header, which
+    /// tonic servers use to cancel the call once it has been exceeded. It is
+    /// encoded with the most precise unit that fits in eight digits.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        let value = MetadataValue::from_str(&duration_to_grpc_timeout(timeout)).unwrap();
+        self.metadata_mut().insert(GRPC_TIMEOUT_HEADER, value);
+    }
+
+    /// The point in time by which the client expects a response.
+    ///
+    /// This is derived from the `grpc-timeout` header when the request is
+    /// received and is `None` if the client did not set a timeout.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// The time left until the deadline, if the client set one.
+    ///
+    /// Returns `Some(Duration::from_secs(0))` once the deadline has passed.
+    pub fn time_remaining(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.deadline.map(|deadline| {
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    /// The address of the client that sent this request.
+    ///
+    /// This is set by [`transport::Server`] from the connection the request
+    /// came in on, and is `None` for connections without an address, like
+    /// Unix domain sockets, and for requests that were not received by a
+    /// server.
+    ///
+    /// [`transport::Server`]: transport/struct.Server.html
+    #[cfg(feature = "transport")]
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.extensions
+            .get::<ConnectInfo>()
+            .and_then(|info| info.remote_addr)
+    }
+
+    /// The certificates the client presented during the TLS handshake,
+    /// leaf first.
+    ///
+    /// This is set by [`transport::Server`] when it is configured with TLS
+    /// and the client authenticated with a certificate, for example when
+    /// the server requires client certificates with
+    /// [`ServerTlsConfig::client_ca_root`].
+    ///
+    /// [`transport::Server`]: transport/struct.Server.html
+    /// [`ServerTlsConfig::client_ca_root`]: transport/server/struct.ServerTlsConfig.html#method.client_ca_root
+    #[cfg(feature = "tls")]
+    pub fn peer_certs(&self) -> Option<Arc<Vec<Certificate>>> {
+        self.extensions
+            .get::<ConnectInfo>()
+            .and_then(|info| info.peer_certs.clone())
+    }
+
     pub(crate) fn from_http_parts(parts: http::request::Parts, message: T) -> Self {
+        let deadline = match try_parse_grpc_timeout(&parts.headers) {
+            Ok(timeout) => timeout.map(|timeout| Instant::now() + timeout),
+            Err(e) => {
+                tracing::trace!("error parsing `grpc-timeout` header: {:?}", e);
+                None
+            }
+        };
+
         Request {
             metadata: MetadataMap::from_headers(parts.headers),
             message,
+            deadline,
+            extensions: parts.extensions,
         }
     }
 
@@ -150,10 +268,35 @@ impl<T> Request<T> {
         *request.method_mut() = http::Method::POST;
         *request.uri_mut() = uri;
         *request.headers_mut() = self.metadata.into_headers();
+        *request.extensions_mut() = self.extensions;
 
         request
     }
 
+    /// Run `interceptor` on everything but the message of this request.
+    pub(crate) fn intercept(self, interceptor: &Interceptor) -> Result<Self, Status> {
+        let Request {
+            metadata,
+            message,
+            deadline,
+            extensions,
+        } = self;
+
+        let request = interceptor.call(Request {
+            metadata,
+            message: (),
+            deadline,
+            extensions,
+        })?;
+
+        Ok(Request {
+            metadata: request.metadata,
+            message,
+            deadline: request.deadline,
+            extensions: request.extensions,
+        })
+    }
+
     #[doc(hidden)]
     pub fn map<F, U>(self, f: F) -> Request<U>
     where
@@ -164,10 +307,72 @@ impl<T> Request<T> {
         Request {
             metadata: self.metadata,
             message,
+            deadline: self.deadline,
+            extensions: self.extensions,
         }
     }
 }
 
+/// Encode a duration in the format described by the gRPC spec, using the most
+/// precise unit whose value fits in at most eight digits.
+pub(crate) fn duration_to_grpc_timeout(duration: Duration) -> String {
+    const MAX_VALUE: u128 = 99_999_999;
+
+    let nanos = duration.as_nanos();
+    let units = [
+        (1, 'n'),
+        (1_000, 'u'),
+        (1_000_000, 'm'),
+        (1_000_000_000, 'S'),
+        (60_000_000_000, 'M'),
+        (3_600_000_000_000, 'H'),
+    ];
+
+    for &(unit_nanos, unit) in &units {
+        // Round up so the peer never sees a shorter timeout than requested.
+        let value = (nanos + unit_nanos - 1) / unit_nanos;
+        if value <= MAX_VALUE {
+            return format!("{}{}", value, unit);
+        }
+    }
+
+    format!("{}H", MAX_VALUE)
+}
+
+/// Parse the `grpc-timeout` header, returning the offending value if it is
+/// malformed.
+pub(crate) fn try_parse_grpc_timeout(
+    headers: &HeaderMap,
+) -> Result<Option<Duration>, &http::HeaderValue> {
+    let value = match headers.get(GRPC_TIMEOUT_HEADER) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    let s = value.to_str().map_err(|_| value)?;
+    if s.len() < 2 || s.len() > 9 {
+        return Err(value);
+    }
+
+    let (digits, unit) = s.split_at(s.len() - 1);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(value);
+    }
+    let amount: u64 = digits.parse().map_err(|_| value)?;
+
+    let duration = match unit {
+        "H" => Duration::from_secs(amount * 60 * 60),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return Err(value),
+    };
+
+    Ok(Some(duration))
+}
+
 impl<T> IntoRequest<T> for T {
     fn into_request(self) -> Request<Self> {
         Request::new(self)
@@ -209,3 +414,105 @@ impl<T> sealed::Sealed for T {}
 mod sealed {
     pub trait Sealed {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &'static str) -> Result<Option<Duration>, ()> {
+        let mut headers = HeaderMap::new();
+        headers.insert(GRPC_TIMEOUT_HEADER, http::HeaderValue::from_static(value));
+        try_parse_grpc_timeout(&headers).map_err(drop)
+    }
+
+    #[test]
+    fn encodes_timeout_with_most_precise_unit() {
+        assert_eq!(duration_to_grpc_timeout(Duration::from_nanos(250)), "250n");
+        assert_eq!(
+            duration_to_grpc_timeout(Duration::from_millis(150)),
+            "150000u"
+        );
+        assert_eq!(
+            duration_to_grpc_timeout(Duration::from_secs(300)),
+            "300000m"
+        );
+        assert_eq!(
+            duration_to_grpc_timeout(Duration::from_secs(200_000)),
+            "200000S"
+        );
+        assert_eq!(
+            duration_to_grpc_timeout(Duration::from_secs(6_000_000_000)),
+            "1666667H"
+        );
+    }
+
+    #[test]
+    fn parses_timeout_header() {
+        assert_eq!(parse("3H"), Ok(Some(Duration::from_secs(3 * 60 * 60))));
+        assert_eq!(parse("2M"), Ok(Some(Duration::from_secs(120))));
+        assert_eq!(parse("10S"), Ok(Some(Duration::from_secs(10))));
+        assert_eq!(
+            parse("99999999m"),
+            Ok(Some(Duration::from_millis(99_999_999)))
+        );
+        assert_eq!(parse("5u"), Ok(Some(Duration::from_micros(5))));
+        assert_eq!(parse("7n"), Ok(Some(Duration::from_nanos(7))));
+
+        assert_eq!(parse("123456789S"), Err(()));
+        assert_eq!(parse("10"), Err(()));
+        assert_eq!(parse("S"), Err(()));
+        assert_eq!(parse("-1S"), Err(()));
+        assert_eq!(parse("10s"), Err(()));
+    }
+
+    #[test]
+    fn set_timeout_roundtrips() {
+        let mut request = Request::new(());
+        request.set_timeout(Duration::from_millis(1500));
+
+        let request = Request::from_http(request.into_http(http::Uri::from_static("/")));
+        let remaining = request.time_remaining().unwrap();
+        assert!(remaining <= Duration::from_millis(1500));
+        assert!(remaining > Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn intercept_keeps_message() {
+        let interceptor = Interceptor::new(|mut request: Request<()>| {
+            if request.metadata().get("authorization").is_none() {
+                return Err(Status::new(crate::Code::Unauthenticated, "no token"));
+            }
+            request
+                .metadata_mut()
+                .insert("x-user", MetadataValue::from_static("alice"));
+            Ok(request)
+        });
+
+        let err = Request::new(7).intercept(&interceptor).unwrap_err();
+        assert_eq!(err.code(), crate::Code::Unauthenticated);
+
+        let mut request = Request::new(7);
+        request
+            .metadata_mut()
+            .insert("authorization", MetadataValue::from_static("token"));
+        let request = request.intercept(&interceptor).unwrap();
+        assert_eq!(request.metadata().get("x-user").unwrap(), "alice");
+        assert_eq!(request.into_inner(), 7);
+    }
+
+    #[test]
+    fn extensions_survive_http_roundtrip() {
+        #[derive(Debug, PartialEq)]
+        struct Principal(&'static str);
+
+        let mut request = Request::new(());
+        request.extensions_mut().insert(Principal("alice"));
+
+        let request = Request::from_http(request.into_http(http::Uri::from_static("/")));
+        let request = request.map(|()| 1);
+        assert_eq!(
+            request.extensions().get::<Principal>(),
+            Some(&Principal("alice"))
+        );
+    }
+}
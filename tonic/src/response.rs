@@ -1,10 +1,12 @@
 use crate::metadata::MetadataMap;
+use http::Extensions;
 
 /// A gRPC response and metadata from an RPC call.
 #[derive(Debug)]
 pub struct Response<T> {
     metadata: MetadataMap,
     message: T,
+    extensions: Extensions,
 }
 
 impl<T> Response<T> {
@@ -24,6 +26,7 @@ impl<T> Response<T> {
         Response {
             metadata: MetadataMap::new(),
             message,
+            extensions: Extensions::new(),
         }
     }
 
@@ -47,17 +50,36 @@ impl<T> Response<T> {
         &mut self.metadata
     }
 
+    /// Get a reference to the response extensions.
+    ///
+    /// Extensions carry typed values alongside the response that are never
+    /// sent over the wire. On the client they are the extensions of the
+    /// underlying `http::Response`, and on the server they are handed to the
+    /// `http::Response` that is sent, where tower layers can read them.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Get a mutable reference to the response extensions.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
     /// Consumes `self`, returning the message
     pub fn into_inner(self) -> T {
         self.message
     }
 
-    pub(crate) fn into_parts(self) -> (MetadataMap, T) {
-        (self.metadata, self.message)
+    pub(crate) fn into_parts(self) -> (MetadataMap, T, Extensions) {
+        (self.metadata, self.message, self.extensions)
     }
 
-    pub(crate) fn from_parts(metadata: MetadataMap, message: T) -> Self {
-        Self { metadata, message }
+    pub(crate) fn from_parts(metadata: MetadataMap, message: T, extensions: Extensions) -> Self {
+        Self {
+            metadata,
+            message,
+            extensions,
+        }
     }
 
     pub(crate) fn from_http(res: http::Response<T>) -> Self {
@@ -65,6 +87,7 @@ impl<T> Response<T> {
         Response {
             metadata: MetadataMap::from_headers(head.headers),
             message,
+            extensions: head.extensions,
         }
     }
 
@@ -73,6 +96,7 @@ impl<T> Response<T> {
 
         *res.version_mut() = http::Version::HTTP_2;
         *res.headers_mut() = self.metadata.into_headers();
+        *res.extensions_mut() = self.extensions;
 
         res
     }
@@ -86,6 +110,7 @@ impl<T> Response<T> {
         Response {
             metadata: self.metadata,
             message,
+            extensions: self.extensions,
         }
     }
 }
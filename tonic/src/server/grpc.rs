@@ -1,14 +1,17 @@
 use crate::{
     body::BoxBody,
-    codec::{encode_server, Codec, Streaming},
+    codec::{
+        encode_server, Codec, CompressionConfig, CompressionEncoding, EnabledCompressionEncodings,
+        Streaming, ACCEPT_ENCODING_HEADER, ENCODING_HEADER,
+    },
     server::{ClientStreamingService, ServerStreamingService, StreamingService, UnaryService},
-    Code, Request, Response, Status,
+    Code, Interceptor, Request, Response, Status,
 };
 use bytes::Bytes;
 use futures_core::TryStream;
 use futures_util::{future, stream, TryStreamExt};
 use http_body::Body;
-use std::fmt;
+use std::{fmt, future::Future, time::Instant};
 
 /// A gRPC Server handler.
 ///
@@ -21,6 +24,11 @@ use std::fmt;
 /// implements some [`Body`].
 pub struct Grpc<T> {
     codec: T,
+    /// Which compression encodings does the server accept for requests?
+    accept_compression_encodings: EnabledCompressionEncodings,
+    /// Which compression encodings might the server use for responses.
+    send_compression_encodings: EnabledCompressionEncodings,
+    interceptor: Option<Interceptor>,
 }
 
 impl<T> Grpc<T>
@@ -30,7 +38,73 @@ where
 {
     /// Creates a new gRPC client with the provided [`Codec`].
     pub fn new(codec: T) -> Self {
-        Self { codec }
+        Self {
+            codec,
+            accept_compression_encodings: EnabledCompressionEncodings::default(),
+            send_compression_encodings: EnabledCompressionEncodings::default(),
+            interceptor: None,
+        }
+    }
+
+    /// Creates a new gRPC server handler with the provided [`Codec`] that
+    /// runs every request through `interceptor` before handing it to the
+    /// service.
+    ///
+    /// A request the interceptor rejects is answered with the returned
+    /// [`Status`] without calling the service.
+    pub fn with_interceptor(codec: T, interceptor: impl Into<Interceptor>) -> Self {
+        Self {
+            interceptor: Some(interceptor.into()),
+            ..Self::new(codec)
+        }
+    }
+
+    /// Enable accepting `gzip` compressed requests.
+    ///
+    /// If a request with an unsupported encoding is received the server will respond with
+    /// `Code::Unimplemented`.
+    pub fn accept_gzip(self) -> Self {
+        self.accept_compressed(CompressionEncoding::gzip())
+    }
+
+    /// Enable sending `gzip` compressed responses.
+    ///
+    /// Requires the client to also support receiving compressed responses, which it
+    /// advertises through the `grpc-accept-encoding` header.
+    pub fn send_gzip(self) -> Self {
+        self.send_compressed(CompressionEncoding::gzip())
+    }
+
+    /// Enable accepting requests compressed with `encoding`.
+    ///
+    /// Encodings are advertised to clients in the order they were enabled.
+    pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+        self.accept_compression_encodings.enable(encoding);
+        self
+    }
+
+    /// Enable sending responses compressed with `encoding`.
+    ///
+    /// The encoding is only used if the client advertises it through the
+    /// `grpc-accept-encoding` header.
+    pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+        self.send_compression_encodings.enable(encoding);
+        self
+    }
+
+    #[doc(hidden)]
+    pub fn apply_compression_config(
+        self,
+        accept_encodings: EnabledCompressionEncodings,
+        send_encodings: EnabledCompressionEncodings,
+    ) -> Self {
+        Self {
+            accept_compression_encodings: self
+                .accept_compression_encodings
+                .merge(&accept_encodings),
+            send_compression_encodings: self.send_compression_encodings.merge(&send_encodings),
+            ..self
+        }
     }
 
     /// Handle a single unary gRPC request.
@@ -45,22 +119,28 @@ where
         B::Data: Into<Bytes> + Send,
         B::Error: Into<crate::Error> + Send,
     {
-        let request = match self.map_request_unary(req).await {
+        let (accept_encodings, send_encodings) = self.compression_encodings(&req);
+        let accept_encoding =
+            CompressionEncoding::from_accept_encoding_header(req.headers(), &send_encodings);
+
+        let request = match self.map_request_unary(req, &accept_encodings).await {
             Ok(r) => r,
             Err(status) => {
                 return self
-                    .map_response::<stream::Once<future::Ready<Result<T::Encode, Status>>>>(Err(
-                        status,
-                    ));
+                    .map_response::<stream::Once<future::Ready<Result<T::Encode, Status>>>>(
+                        Err(status),
+                        accept_encoding,
+                        &accept_encodings,
+                    );
             }
         };
 
-        let response = service
-            .call(request)
+        let deadline = request.deadline();
+        let response = with_deadline(deadline, service.call(request))
             .await
             .map(|r| r.map(|m| stream::once(future::ok(m))));
 
-        self.map_response(response)
+        self.map_response(response, accept_encoding, &accept_encodings)
     }
 
     /// Handle a server side streaming request.
@@ -76,16 +156,25 @@ where
         B::Data: Into<Bytes> + Send,
         B::Error: Into<crate::Error> + Send,
     {
-        let request = match self.map_request_unary(req).await {
+        let (accept_encodings, send_encodings) = self.compression_encodings(&req);
+        let accept_encoding =
+            CompressionEncoding::from_accept_encoding_header(req.headers(), &send_encodings);
+
+        let request = match self.map_request_unary(req, &accept_encodings).await {
             Ok(r) => r,
             Err(status) => {
-                return self.map_response::<S::ResponseStream>(Err(status));
+                return self.map_response::<S::ResponseStream>(
+                    Err(status),
+                    accept_encoding,
+                    &accept_encodings,
+                );
             }
         };
 
-        let response = service.call(request).await;
+        let deadline = request.deadline();
+        let response = with_deadline(deadline, service.call(request)).await;
 
-        self.map_response(response)
+        self.map_response(response, accept_encoding, &accept_encodings)
     }
 
     /// Handle a client side streaming gRPC request.
@@ -100,12 +189,27 @@ where
         B::Data: Into<Bytes> + Send + 'static,
         B::Error: Into<crate::Error> + Send + 'static,
     {
-        let request = self.map_request_streaming(req);
-        let response = service
-            .call(request)
+        let (accept_encodings, send_encodings) = self.compression_encodings(&req);
+        let accept_encoding =
+            CompressionEncoding::from_accept_encoding_header(req.headers(), &send_encodings);
+
+        let request = match self.map_request_streaming(req, &accept_encodings) {
+            Ok(r) => r,
+            Err(status) => {
+                return self
+                    .map_response::<stream::Once<future::Ready<Result<T::Encode, Status>>>>(
+                        Err(status),
+                        accept_encoding,
+                        &accept_encodings,
+                    );
+            }
+        };
+
+        let deadline = request.deadline();
+        let response = with_deadline(deadline, service.call(request))
             .await
             .map(|r| r.map(|m| stream::once(future::ok(m))));
-        self.map_response(response)
+        self.map_response(response, accept_encoding, &accept_encodings)
     }
 
     /// Handle a bi-directional streaming gRPC request.
@@ -121,22 +225,79 @@ where
         B::Data: Into<Bytes> + Send,
         B::Error: Into<crate::Error> + Send,
     {
-        let request = self.map_request_streaming(req);
-        let response = service.call(request).await;
-        self.map_response(response)
+        let (accept_encodings, send_encodings) = self.compression_encodings(&req);
+        let accept_encoding =
+            CompressionEncoding::from_accept_encoding_header(req.headers(), &send_encodings);
+
+        let request = match self.map_request_streaming(req, &accept_encodings) {
+            Ok(r) => r,
+            Err(status) => {
+                return self.map_response::<S::ResponseStream>(
+                    Err(status),
+                    accept_encoding,
+                    &accept_encodings,
+                );
+            }
+        };
+
+        let deadline = request.deadline();
+        let response = with_deadline(deadline, service.call(request)).await;
+        self.map_response(response, accept_encoding, &accept_encodings)
+    }
+
+    /// Combine the encodings configured on this handler with any defaults the
+    /// transport attached to the request.
+    fn compression_encodings<B>(
+        &self,
+        request: &http::Request<B>,
+    ) -> (EnabledCompressionEncodings, EnabledCompressionEncodings) {
+        match request.extensions().get::<CompressionConfig>() {
+            Some(config) => (
+                self.accept_compression_encodings.merge(&config.accept),
+                self.send_compression_encodings.merge(&config.send),
+            ),
+            None => (
+                self.accept_compression_encodings.clone(),
+                self.send_compression_encodings.clone(),
+            ),
+        }
+    }
+
+    /// Run the request through the interceptor the transport attached to it,
+    /// if any, and then through the one configured on this handler.
+    fn intercept<M>(&self, mut request: Request<M>) -> Result<Request<M>, Status> {
+        if let Some(interceptor) = request.extensions_mut().remove::<Interceptor>() {
+            request = request.intercept(&interceptor)?;
+        }
+
+        match &self.interceptor {
+            Some(interceptor) => request.intercept(interceptor),
+            None => Ok(request),
+        }
     }
 
     async fn map_request_unary<B>(
         &mut self,
         request: http::Request<B>,
+        accept_encodings: &EnabledCompressionEncodings,
     ) -> Result<Request<T::Decode>, Status>
     where
         B: Body + Send + Sync + 'static,
         B::Data: Into<Bytes> + Send,
         B::Error: Into<crate::Error> + Send,
     {
+        let request_compression_encoding =
+            CompressionEncoding::from_encoding_header(request.headers(), accept_encodings)?;
+        let max_decompressed_size = max_decompressed_size(&request);
+
         let (parts, body) = request.into_parts();
-        let stream = Streaming::new_request(self.codec.decoder(), body);
+
+        // Intercept before reading the body so rejected calls are cheap.
+        let req = self.intercept(Request::from_http_parts(parts, ()))?;
+
+        let stream =
+            Streaming::new_request(self.codec.decoder(), body, request_compression_encoding)
+                .max_decompressed_size(max_decompressed_size);
 
         futures_util::pin_mut!(stream);
 
@@ -145,7 +306,7 @@ where
             .await?
             .ok_or_else(|| Status::new(Code::Internal, "Missing request message."))?;
 
-        let mut req = Request::from_http_parts(parts, message);
+        let mut req = req.map(|()| message);
 
         if let Some(trailers) = stream.trailers().await? {
             req.metadata_mut().merge(trailers);
@@ -157,18 +318,30 @@ where
     fn map_request_streaming<B>(
         &mut self,
         request: http::Request<B>,
-    ) -> Request<Streaming<T::Decode>>
+        accept_encodings: &EnabledCompressionEncodings,
+    ) -> Result<Request<Streaming<T::Decode>>, Status>
     where
         B: Body + Send + Sync + 'static,
         B::Data: Into<Bytes> + Send,
         B::Error: Into<crate::Error> + Send,
     {
-        Request::from_http(request.map(|body| Streaming::new_request(self.codec.decoder(), body)))
+        let encoding =
+            CompressionEncoding::from_encoding_header(request.headers(), accept_encodings)?;
+        let max_decompressed_size = max_decompressed_size(&request);
+
+        let request = request.map(|body| {
+            Streaming::new_request(self.codec.decoder(), body, encoding)
+                .max_decompressed_size(max_decompressed_size)
+        });
+
+        self.intercept(Request::from_http(request))
     }
 
     fn map_response<B>(
         &mut self,
         response: Result<crate::Response<B>, Status>,
+        accept_encoding: Option<CompressionEncoding>,
+        accept_encodings: &EnabledCompressionEncodings,
     ) -> http::Response<BoxBody>
     where
         B: TryStream<Ok = T::Encode, Error = Status> + Send + Sync + 'static,
@@ -183,7 +356,18 @@ where
                     http::header::HeaderValue::from_static("application/grpc"),
                 );
 
-                let body = encode_server(self.codec.encoder(), body.into_stream());
+                if let Some(encoding) = &accept_encoding {
+                    // Set the content encoding
+                    parts
+                        .headers
+                        .insert(ENCODING_HEADER, encoding.to_header_value());
+                }
+
+                if let Some(value) = accept_encodings.to_accept_encoding_header_value() {
+                    parts.headers.insert(ACCEPT_ENCODING_HEADER, value);
+                }
+
+                let body = encode_server(self.codec.encoder(), body.into_stream(), accept_encoding);
 
                 http::Response::from_parts(parts, BoxBody::new(body))
             }
@@ -195,6 +379,10 @@ where
                     http::header::HeaderValue::from_static("application/grpc"),
                 );
 
+                if let Some(value) = accept_encodings.to_accept_encoding_header_value() {
+                    parts.headers.insert(ACCEPT_ENCODING_HEADER, value);
+                }
+
                 status.add_header(&mut parts.headers).unwrap();
 
                 http::Response::from_parts(parts, BoxBody::empty())
@@ -203,6 +391,39 @@ where
     }
 }
 
+/// The decompression limit the transport attached to the request, if any.
+fn max_decompressed_size<B>(request: &http::Request<B>) -> Option<usize> {
+    request
+        .extensions()
+        .get::<CompressionConfig>()
+        .and_then(|config| config.max_decompressed_size)
+}
+
+/// Drive the handler future, failing with `DeadlineExceeded` if the client's
+/// deadline passes before it completes.
+#[cfg(feature = "transport")]
+async fn with_deadline<F, R>(deadline: Option<Instant>, fut: F) -> Result<R, Status>
+where
+    F: Future<Output = Result<R, Status>>,
+{
+    match deadline {
+        Some(deadline) => tokio::timer::Timeout::new_at(fut, deadline)
+            .await
+            .unwrap_or_else(|_| Err(Status::new(Code::DeadlineExceeded, "Deadline exceeded"))),
+        None => fut.await,
+    }
+}
+
+/// Without the `transport` feature there is no timer to cancel the handler
+/// with, so the deadline is only made available through the request.
+#[cfg(not(feature = "transport"))]
+async fn with_deadline<F, R>(_deadline: Option<Instant>, fut: F) -> Result<R, Status>
+where
+    F: Future<Output = Result<R, Status>>,
+{
+    fut.await
+}
+
 impl<T> fmt::Debug for Grpc<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Grpc").finish()
@@ -62,6 +62,20 @@ impl Status {
         }
     }
 
+    /// Create a new `Status` with the associated code, message and binary
+    /// details.
+    ///
+    /// The details are sent in the `grpc-status-details-bin` trailer. By
+    /// convention they hold an encoded `google.rpc.Status` message; see
+    /// `Status::with_error_details` for a typed way to build them.
+    pub fn with_details(code: Code, message: impl Into<String>, details: Bytes) -> Status {
+        Status {
+            code,
+            message: message.into(),
+            details,
+        }
+    }
+
     /// Create a new `Unimplemented` status with the associated message.
     pub fn unimplemented(message: impl Into<String>) -> Status {
         Status {
@@ -155,7 +169,13 @@ impl Status {
                 .unwrap_or_else(|| Ok(String::new()));
             let details = header_map
                 .get(GRPC_STATUS_DETAILS_HEADER)
-                .map(|h| Bytes::from(h.as_bytes()))
+                .and_then(|h| match base64::decode(h.as_bytes()) {
+                    Ok(details) => Some(Bytes::from(details)),
+                    Err(err) => {
+                        warn!("Error decoding status details header: {}", err);
+                        None
+                    }
+                })
                 .unwrap_or_else(Bytes::new);
             match error_message {
                 Ok(message) => Status {
@@ -220,10 +240,11 @@ impl Status {
         }
 
         if !self.details.is_empty() {
+            let details = base64::encode_config(&self.details[..], base64::STANDARD_NO_PAD);
+
             header_map.insert(
                 GRPC_STATUS_DETAILS_HEADER,
-                HeaderValue::from_shared(self.details.clone())
-                    .map_err(invalid_header_value_byte)?,
+                HeaderValue::from_shared(details.into()).map_err(invalid_header_value_byte)?,
             );
         }
 
@@ -489,6 +510,19 @@ mod tests {
         assert_eq!(err.reason(), Some(h2::Reason::CANCEL));
     }
 
+    #[test]
+    fn details_roundtrip() {
+        let details = Bytes::from(&[0u8, 1, 2, 0xff, b'\n'][..]);
+        let orig = Status::with_details(Code::InvalidArgument, "bad", details.clone());
+
+        let header_map = orig.to_header_map().unwrap();
+        let found = Status::from_header_map(&header_map).unwrap();
+
+        assert_eq!(found.code(), Code::InvalidArgument);
+        assert_eq!(found.message(), "bad");
+        assert_eq!(found.details(), &details[..]);
+    }
+
     #[test]
     fn code_from_i32() {
         // This for loop should catch if we ever add a new variant and don't
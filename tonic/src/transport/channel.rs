@@ -1,11 +1,20 @@
 //! Client implementation and builder.
 
 use super::{
-    service::{Connection, ServiceList},
+    dns::{GaiResolver, Resolve},
+    service::{
+        Balancer, Connection, Connectivity, DnsDiscover, DynamicServiceStream, FailFast, Hedge,
+        Retry, RetryFuture, ServiceList,
+    },
     Endpoint,
 };
-use crate::{body::BoxBody, client::GrpcService};
+use crate::{
+    body::BoxBody,
+    client::GrpcService,
+    codec::{CompressionEncoding, EnabledCompressionEncodings},
+};
 use bytes::Bytes;
+use futures_core::Stream;
 use http::{
     uri::{InvalidUriBytes, Uri},
     Request, Response,
@@ -13,9 +22,15 @@ use http::{
 use std::{
     fmt,
     future::Future,
+    hash::Hash,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::{
+    mpsc::{self, Sender},
+    watch,
 };
 use tower::{
     buffer::{self, Buffer},
@@ -24,10 +39,74 @@ use tower::{
     Service,
 };
 use tower_balance::p2c::Balance;
+use tower_make::MakeConnection;
 
-type Svc = Either<Connection, BoxService<Request<BoxBody>, Response<hyper::Body>, crate::Error>>;
+pub use super::service::{HedgingPolicy, ResponseBody, RetryPolicy};
+pub use tower::discover::Change;
+
+/// How a balanced [`Channel`] picks the endpoint a request is sent to.
+///
+/// [`Channel`]: struct.Channel.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadBalancingPolicy {
+    /// Pick the less loaded of two random endpoints.
+    ///
+    /// This is the default.
+    PowerOfTwoChoices,
+    /// Send every request to the first endpoint that is available, in the
+    /// order they were discovered, failing over to the next one while it is
+    /// not.
+    PickFirst,
+    /// Cycle through the available endpoints.
+    RoundRobin,
+    /// Cycle through the available endpoints, in proportion to their
+    /// [`weight`].
+    ///
+    /// [`weight`]: ../struct.Endpoint.html#method.weight
+    WeightedRoundRobin,
+}
+
+impl Default for LoadBalancingPolicy {
+    fn default() -> Self {
+        LoadBalancingPolicy::PowerOfTwoChoices
+    }
+}
+
+/// The connectivity state of a [`Channel`], following the gRPC
+/// [connectivity semantics].
+///
+/// A balanced channel is in the best state of any of its connections, so it
+/// is `Ready` as long as one endpoint is.
+///
+/// [`Channel`]: struct.Channel.html
+/// [connectivity semantics]: https://github.com/grpc/grpc/blob/master/doc/connectivity-semantics-and-api.md
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectivityState {
+    /// There is no connection and none is being established, until a
+    /// request needs one.
+    Idle,
+    /// A connection is being established.
+    Connecting,
+    /// A connection is established and can take requests.
+    Ready,
+    /// Establishing a connection failed, another attempt is made after a
+    /// backoff. Requests fail with `Code::Unavailable` in the meantime,
+    /// unless the channel waits for ready.
+    TransientFailure,
+}
+
+impl Default for ConnectivityState {
+    fn default() -> Self {
+        ConnectivityState::Idle
+    }
+}
+
+type Svc = FailFast<
+    Either<Connection, BoxService<Request<BoxBody>, Response<ResponseBody>, crate::Error>>,
+>;
 
 const DEFAULT_BUFFER_SIZE: usize = 1024;
+const DEFAULT_RESOLVE_INTERVAL: Duration = Duration::from_secs(30);
 
 /// A default batteries included `transport` channel.
 ///
@@ -52,17 +131,43 @@ const DEFAULT_BUFFER_SIZE: usize = 1024;
 /// the channel is backed by a `tower_buffer::Buffer` which runs the connection
 /// in a background task and provides a `mpsc` channel interface. Due to this
 /// cloning the `Channel` type is cheap and encouraged.
+///
+/// # Failing fast
+///
+/// A channel whose connections all fail to connect does not hold requests
+/// until one comes back. Once every connection is in
+/// [`ConnectivityState::TransientFailure`], requests waiting for a connection,
+/// and those sent while it lasts, fail with `Code::Unavailable`. Connections
+/// that are still being established, or that are backing off while others are
+/// up, only delay requests.
+///
+/// To hold requests until a connection is established instead, set
+/// [`Endpoint::wait_for_ready`].
+///
+/// [`ConnectivityState::TransientFailure`]: enum.ConnectivityState.html#variant.TransientFailure
+/// [`Endpoint::wait_for_ready`]: ../struct.Endpoint.html#method.wait_for_ready
 #[derive(Clone)]
 pub struct Channel {
     svc: Buffer<Svc, Request<BoxBody>>,
     interceptor_headers: Option<Arc<dyn Fn(&mut http::HeaderMap) + Send + Sync + 'static>>,
+    retry: Option<Retry>,
+    hedge: Option<Hedge>,
+    send_compression: Option<CompressionEncoding>,
+    accept_compression: EnabledCompressionEncodings,
+    max_decompressed_size: Option<usize>,
+    connectivity: Connectivity,
 }
 
 /// A future that resolves to an HTTP response.
 ///
 /// This is returned by the `Service::call` on [`Channel`].
 pub struct ResponseFuture {
-    inner: buffer::future::ResponseFuture<<Svc as Service<Request<BoxBody>>>::Future>,
+    inner: Inner,
+}
+
+enum Inner {
+    Buffered(buffer::future::ResponseFuture<<Svc as Service<Request<BoxBody>>>::Future>),
+    Boxed(RetryFuture),
 }
 
 impl Channel {
@@ -73,89 +178,301 @@ impl Channel {
 
     /// Create an `Endpoint` from a static string.
     ///
+    /// This accepts the same URIs as [`Endpoint::from_static`], including
+    /// `unix:///path/to/socket`.
+    ///
     /// ```
     /// # use tonic::transport::Channel;
     /// Channel::from_static("https://example.com");
     /// ```
+    ///
+    /// [`Endpoint::from_static`]: ../struct.Endpoint.html#method.from_static
     pub fn from_static(s: &'static str) -> Endpoint {
-        let uri = Uri::from_static(s);
-        Self::builder(uri)
+        Endpoint::from_static(s)
     }
 
     /// Create an `Endpoint` from shared bytes.
     ///
+    /// This accepts the same URIs as [`Endpoint::from_shared`], including
+    /// `unix:///path/to/socket`.
+    ///
     /// ```
     /// # use tonic::transport::Channel;
     /// Channel::from_shared("https://example.com");
     /// ```
+    ///
+    /// [`Endpoint::from_shared`]: ../struct.Endpoint.html#method.from_shared
     pub fn from_shared(s: impl Into<Bytes>) -> Result<Endpoint, InvalidUriBytes> {
-        let uri = Uri::from_shared(s.into())?;
-        Ok(Self::builder(uri))
+        Endpoint::from_shared(s)
     }
 
     /// Balance a list of [`Endpoint`]'s.
     ///
     /// This creates a [`Channel`] that will load balance accross all the
-    /// provided endpoints.
+    /// provided endpoints. Each endpoint is connected to lazily with its own
+    /// connection settings, and one that fails to connect backs off while
+    /// requests go to the others. The settings that apply to the whole
+    /// channel are taken from the first endpoint, see [`balance_channel`].
+    ///
+    /// [`balance_channel`]: #method.balance_channel
     pub fn balance_list(list: impl Iterator<Item = Endpoint>) -> Self {
         let list = list.collect::<Vec<_>>();
+        let template = list.first().cloned();
 
-        let buffer_size = list
-            .iter()
-            .next()
-            .and_then(|e| e.buffer_size)
-            .unwrap_or(DEFAULT_BUFFER_SIZE);
+        let connectivity = Connectivity::new();
+        let discover = ServiceList::new(list, connectivity.clone());
+
+        Self::balance(discover, template.as_ref(), connectivity)
+    }
+
+    /// Balance across [`Endpoint`]'s that are added and removed at runtime.
+    ///
+    /// This creates a [`Channel`] along with a sender through which
+    /// [`Change`]'s are fed to it. Each endpoint is identified by a key of
+    /// the caller's choosing, which is used to remove it again. Removed
+    /// endpoints are no longer picked for new requests, while requests
+    /// already in flight on them are allowed to finish.
+    ///
+    /// Every inserted endpoint is connected to with its own URI, TLS,
+    /// timeouts, limits, keepalive, reconnect backoff, load measure and
+    /// weight. The settings that apply to the whole channel are taken from
+    /// `template` instead: the buffer size, header interceptor, load
+    /// balancing policy, retry or hedging policy, compression, decompression
+    /// limit and whether to wait for ready. The URI and connection settings of `template`
+    /// are not used.
+    ///
+    /// ```
+    /// # use tonic::transport::{Channel, Endpoint};
+    /// # use tonic::transport::channel::{Change, RetryPolicy};
+    /// # async fn docs() {
+    /// let template = Endpoint::from_static("http://[::1]").retry_policy(RetryPolicy::new());
+    /// let (channel, mut tx) = Channel::balance_channel(template, 16);
+    ///
+    /// let endpoint = Endpoint::from_static("http://[::1]:50051");
+    /// tx.send(Change::Insert("a", endpoint)).await.unwrap();
+    /// tx.send(Change::Remove("a")).await.unwrap();
+    /// # drop(channel);
+    /// # }
+    /// ```
+    ///
+    /// [`Change`]: enum.Change.html
+    pub fn balance_channel<K>(
+        template: Endpoint,
+        capacity: usize,
+    ) -> (Self, Sender<Change<K, Endpoint>>)
+    where
+        K: Hash + Eq + Send + Clone + Unpin + 'static,
+    {
+        let (tx, rx) = mpsc::channel(capacity);
+        (Self::balance_stream(template, rx), tx)
+    }
+
+    /// Balance across [`Endpoint`]'s following a stream of [`Change`]'s.
+    ///
+    /// This works like [`balance_channel`] for callers that already have
+    /// their membership changes as a `Stream`, for example from a service
+    /// registry. Once the stream ends the channel keeps the endpoints it has.
+    /// The settings that apply to the whole channel are taken from
+    /// `template`, as described for [`balance_channel`].
+    ///
+    /// [`Change`]: enum.Change.html
+    /// [`balance_channel`]: #method.balance_channel
+    pub fn balance_stream<K, S>(template: Endpoint, changes: S) -> Self
+    where
+        K: Hash + Eq + Send + Clone + Unpin + 'static,
+        S: Stream<Item = Change<K, Endpoint>> + Unpin + Send + 'static,
+    {
+        let connectivity = Connectivity::new();
+        let discover = DynamicServiceStream::new(changes, connectivity.clone());
 
-        let interceptor_headers = list
-            .iter()
-            .next()
-            .and_then(|e| e.interceptor_headers.clone());
+        Self::balance(discover, Some(&template), connectivity)
+    }
 
-        let discover = ServiceList::new(list);
+    /// Balance across all the addresses a DNS name resolves to.
+    ///
+    /// The target is either `dns:///host:port` or plain `host:port`. All A and
+    /// AAAA records of the host are connected to, and the host is re-resolved
+    /// every 30 seconds to pick up addresses that were added or removed.
+    ///
+    /// The connections use plaintext HTTP/2 with default settings. To use
+    /// TLS or otherwise configure them, build an [`Endpoint`] for the host
+    /// and use [`balance_resolver`] with a [`GaiResolver`].
+    ///
+    /// ```no_run
+    /// # use tonic::transport::Channel;
+    /// let channel = Channel::balance_dns("dns:///my-service.default.svc:50051");
+    /// ```
+    ///
+    /// [`Endpoint`]: struct.Endpoint.html
+    /// [`balance_resolver`]: #method.balance_resolver
+    /// [`GaiResolver`]: dns/struct.GaiResolver.html
+    pub fn balance_dns(target: impl AsRef<str>) -> Result<Self, super::Error> {
+        let target = target.as_ref();
+        let host_port = if target.starts_with("dns:///") {
+            &target[7..]
+        } else {
+            target
+        };
 
-        Self::balance(discover, buffer_size, interceptor_headers)
+        let endpoint = Endpoint::from_shared(format!("http://{}", host_port))
+            .map_err(|e| super::Error::from_source(super::ErrorKind::Client, e.into()))?;
+
+        Ok(Self::balance_resolver(
+            endpoint,
+            GaiResolver::new(),
+            DEFAULT_RESOLVE_INTERVAL,
+        ))
     }
 
-    pub(crate) async fn connect(endpoint: Endpoint) -> Result<Self, super::Error> {
-        let buffer_size = endpoint.buffer_size.clone().unwrap_or(DEFAULT_BUFFER_SIZE);
-        let interceptor_headers = endpoint.interceptor_headers.clone();
+    /// Balance across all the addresses the host of `endpoint` resolves to
+    /// with `resolver`.
+    ///
+    /// The host is re-resolved every `interval`, and sooner when a connection
+    /// to one of its addresses is lost or cannot be established. Every
+    /// address is connected to with the settings of `endpoint`, which also
+    /// keeps its host as the origin of requests and the name TLS certificates
+    /// are checked against.
+    ///
+    /// ```no_run
+    /// # use tonic::transport::{Channel, Endpoint};
+    /// # use tonic::transport::dns::StaticResolver;
+    /// # use std::time::Duration;
+    /// let resolver = StaticResolver::new();
+    /// resolver.set("example.com", vec!["10.0.0.1".parse().unwrap()]);
+    ///
+    /// let endpoint = Endpoint::from_static("http://example.com:50051");
+    /// let channel = Channel::balance_resolver(endpoint, resolver, Duration::from_secs(30));
+    /// ```
+    pub fn balance_resolver<R>(endpoint: Endpoint, resolver: R, interval: Duration) -> Self
+    where
+        R: Resolve + Unpin,
+    {
+        let template = endpoint.clone();
+
+        let connectivity = Connectivity::new();
+        let discover = DnsDiscover::new(endpoint, resolver, interval, connectivity.clone());
+
+        Self::balance(discover, Some(&template), connectivity)
+    }
+
+    /// The current connectivity state of this channel.
+    pub fn state(&self) -> ConnectivityState {
+        self.connectivity.get()
+    }
 
-        let svc = Connection::new(endpoint)
+    /// Watch the connectivity state of this channel.
+    ///
+    /// The receiver yields the current state first, and then every state
+    /// the channel moves to. Changes that happen in quick succession may be
+    /// coalesced, only the latest state is kept.
+    ///
+    /// ```no_run
+    /// # use tonic::transport::Channel;
+    /// # async fn docs(channel: Channel) {
+    /// let mut states = channel.watch_state();
+    ///
+    /// while let Some(state) = states.recv().await {
+    ///     println!("channel is {:?}", state);
+    /// }
+    /// # }
+    /// ```
+    pub fn watch_state(&self) -> watch::Receiver<ConnectivityState> {
+        self.connectivity.watch()
+    }
+
+    pub(crate) async fn connect<C>(connector: C, endpoint: Endpoint) -> Result<Self, super::Error>
+    where
+        C: MakeConnection<Uri> + Send + 'static,
+        C::Connection: Unpin + Send + 'static,
+        C::Future: Send + 'static,
+        C::Error: Into<crate::Error> + Send,
+    {
+        let connectivity = Connectivity::new();
+        let svc = Connection::new(connector, endpoint.clone(), connectivity.reporter())
             .await
             .map_err(|e| super::Error::from_source(super::ErrorKind::Client, e))?;
 
-        let svc = Buffer::new(Either::A(svc), buffer_size);
+        Ok(Self::with_connection(svc, &endpoint, connectivity))
+    }
 
-        Ok(Channel {
-            svc,
-            interceptor_headers,
-        })
+    pub(crate) fn new<C>(connector: C, endpoint: Endpoint) -> Self
+    where
+        C: MakeConnection<Uri> + Send + 'static,
+        C::Connection: Unpin + Send + 'static,
+        C::Future: Send + 'static,
+        C::Error: Into<crate::Error> + Send,
+    {
+        let connectivity = Connectivity::new();
+        let svc = Connection::lazy(connector, endpoint.clone(), connectivity.reporter());
+
+        Self::with_connection(svc, &endpoint, connectivity)
+    }
+
+    fn with_connection(svc: Connection, endpoint: &Endpoint, connectivity: Connectivity) -> Self {
+        Self::with_service(Either::A(svc), Some(endpoint), connectivity)
     }
 
+    /// Build a channel balancing across `discover`, with the channel-level
+    /// settings of `template` or the defaults.
     pub(crate) fn balance<D>(
         discover: D,
-        buffer_size: usize,
-        interceptor_headers: Option<Arc<dyn Fn(&mut http::HeaderMap) + Send + Sync + 'static>>,
+        template: Option<&Endpoint>,
+        connectivity: Connectivity,
     ) -> Self
     where
         D: Discover<Service = Connection> + Unpin + Send + 'static,
         D::Error: Into<crate::Error>,
-        D::Key: Send + Clone,
+        D::Key: Hash + Eq + Send + Clone,
     {
-        let svc = Balance::from_entropy(discover);
+        let policy = template
+            .map(|e| e.load_balancing_policy)
+            .unwrap_or_default();
+
+        let svc = match policy {
+            LoadBalancingPolicy::PowerOfTwoChoices => {
+                BoxService::new(Balance::from_entropy(discover))
+            }
+            policy => BoxService::new(Balancer::new(discover, policy)),
+        };
+
+        Self::with_service(Either::B(svc), template, connectivity)
+    }
 
-        let svc = BoxService::new(svc);
-        let svc = Buffer::new(Either::B(svc), buffer_size);
+    fn with_service(
+        svc: Either<Connection, BoxService<Request<BoxBody>, Response<ResponseBody>, crate::Error>>,
+        template: Option<&Endpoint>,
+        connectivity: Connectivity,
+    ) -> Self {
+        let buffer_size = template
+            .and_then(|e| e.buffer_size)
+            .unwrap_or(DEFAULT_BUFFER_SIZE);
+        let wait_for_ready = template.map(|e| e.wait_for_ready).unwrap_or(false);
+        let svc = Buffer::new(
+            FailFast::new(svc, connectivity.clone(), wait_for_ready),
+            buffer_size,
+        );
 
         Channel {
             svc,
-            interceptor_headers,
+            interceptor_headers: template.and_then(|e| e.interceptor_headers.clone()),
+            retry: template
+                .and_then(|e| e.retry_policy.clone())
+                .map(Retry::new),
+            hedge: template
+                .and_then(|e| e.hedging_policy.clone())
+                .map(Hedge::new),
+            send_compression: template.and_then(|e| e.send_compression.clone()),
+            accept_compression: template
+                .map(|e| e.accept_compression.clone())
+                .unwrap_or_default(),
+            max_decompressed_size: template.and_then(|e| e.max_decompressed_size),
+            connectivity,
         }
     }
 }
 
 impl GrpcService<BoxBody> for Channel {
-    type ResponseBody = hyper::Body;
+    type ResponseBody = ResponseBody;
     type Error = super::Error;
     type Future = ResponseFuture;
 
@@ -164,22 +481,43 @@ impl GrpcService<BoxBody> for Channel {
             .map_err(|e| super::Error::from_source(super::ErrorKind::Client, e))
     }
 
+    fn send_compression_encoding(&self) -> Option<CompressionEncoding> {
+        self.send_compression.clone()
+    }
+
+    fn accept_compression_encodings(&self) -> EnabledCompressionEncodings {
+        self.accept_compression.clone()
+    }
+
+    fn max_decompressed_message_size(&self) -> Option<usize> {
+        self.max_decompressed_size
+    }
+
     fn call(&mut self, mut request: Request<BoxBody>) -> Self::Future {
         if let Some(interceptor) = self.interceptor_headers.clone() {
             interceptor(request.headers_mut());
         }
 
-        let inner = GrpcService::call(&mut self.svc, request);
+        let inner = match (&self.retry, &self.hedge) {
+            (_, Some(hedge)) => Inner::Boxed(hedge.call(&mut self.svc, request)),
+            (Some(retry), None) => Inner::Boxed(retry.call(&mut self.svc, request)),
+            (None, None) => Inner::Buffered(GrpcService::call(&mut self.svc, request)),
+        };
+
         ResponseFuture { inner }
     }
 }
 
 impl Future for ResponseFuture {
-    type Output = Result<Response<hyper::Body>, super::Error>;
+    type Output = Result<Response<ResponseBody>, super::Error>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let val = futures_util::ready!(Pin::new(&mut self.inner).poll(cx))
-            .map_err(|e| super::Error::from_source(super::ErrorKind::Client, e))?;
+        let res = match &mut self.inner {
+            Inner::Buffered(fut) => futures_util::ready!(Pin::new(fut).poll(cx)),
+            Inner::Boxed(fut) => futures_util::ready!(fut.as_mut().poll(cx)),
+        };
+
+        let val = res.map_err(|e| super::Error::from_source(super::ErrorKind::Client, e))?;
         Ok(val).into()
     }
 }
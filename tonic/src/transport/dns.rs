@@ -0,0 +1,119 @@
+//! Name resolution for balanced channels.
+//!
+//! A [`Channel`] created with [`Channel::balance_dns`] or
+//! [`Channel::balance_resolver`] resolves the host of its endpoint into all
+//! of its addresses, connects to each of them and periodically re-resolves
+//! the host to follow addresses that come and go.
+//!
+//! [`Channel`]: ../channel/struct.Channel.html
+//! [`Channel::balance_dns`]: ../channel/struct.Channel.html#method.balance_dns
+//! [`Channel::balance_resolver`]: ../channel/struct.Channel.html#method.balance_resolver
+
+use hyper::client::connect::dns::{self, Name, Resolve as _};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+/// The future returned by [`Resolve::resolve`].
+///
+/// [`Resolve::resolve`]: trait.Resolve.html#tymethod.resolve
+pub type ResolveFuture = Pin<Box<dyn Future<Output = io::Result<Vec<IpAddr>>> + Send + 'static>>;
+
+/// Resolves host names into IP addresses.
+pub trait Resolve: Send + 'static {
+    /// Resolve `host` into all the addresses it currently points at.
+    fn resolve(&mut self, host: &str) -> ResolveFuture;
+}
+
+/// Resolves host names with the system's `getaddrinfo`, which yields all the
+/// A and AAAA records of a host.
+#[derive(Debug, Clone)]
+pub struct GaiResolver {
+    inner: dns::GaiResolver,
+}
+
+impl GaiResolver {
+    /// Create a new `GaiResolver`.
+    pub fn new() -> Self {
+        GaiResolver {
+            inner: dns::GaiResolver::new(),
+        }
+    }
+}
+
+impl Default for GaiResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolve for GaiResolver {
+    fn resolve(&mut self, host: &str) -> ResolveFuture {
+        let name = host.parse::<Name>().expect("parsing a name is infallible");
+        let resolving = self.inner.resolve(name);
+
+        Box::pin(async move { Ok(resolving.await?.collect()) })
+    }
+}
+
+/// Resolves host names from an in-memory table.
+///
+/// Clones share the same table, so addresses can be changed while a channel
+/// is using the resolver. This is mostly useful for tests.
+///
+/// ```
+/// # use tonic::transport::dns::StaticResolver;
+/// let resolver = StaticResolver::new();
+/// resolver.set("example.com", vec!["10.0.0.1".parse().unwrap()]);
+/// ```
+#[derive(Clone, Default)]
+pub struct StaticResolver {
+    hosts: Arc<Mutex<HashMap<String, Vec<IpAddr>>>>,
+}
+
+impl StaticResolver {
+    /// Create a new, empty `StaticResolver`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the addresses `host` resolves to.
+    pub fn set(&self, host: impl Into<String>, addrs: Vec<IpAddr>) {
+        self.hosts.lock().unwrap().insert(host.into(), addrs);
+    }
+
+    /// Remove `host`, so that resolving it fails.
+    pub fn remove(&self, host: &str) {
+        self.hosts.lock().unwrap().remove(host);
+    }
+}
+
+impl Resolve for StaticResolver {
+    fn resolve(&mut self, host: &str) -> ResolveFuture {
+        let res = self
+            .hosts
+            .lock()
+            .unwrap()
+            .get(host)
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("unknown host {}", host))
+            });
+
+        Box::pin(async move { res })
+    }
+}
+
+impl fmt::Debug for StaticResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticResolver")
+            .field("hosts", &*self.hosts.lock().unwrap())
+            .finish()
+    }
+}
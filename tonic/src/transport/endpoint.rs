@@ -1,17 +1,27 @@
-use super::channel::Channel;
+use super::{
+    channel::{Channel, HedgingPolicy, LoadBalancingPolicy, RetryPolicy},
+    service::{box_layer, Backoff, BoxLayer, DefaultConnector, LoadKind},
+};
 #[cfg(feature = "tls")]
 use super::{
     service::TlsConnector,
     tls::{Certificate, Identity, TlsProvider},
 };
-use bytes::Bytes;
+use crate::{
+    body::BoxBody,
+    codec::{CompressionEncoding, EnabledCompressionEncodings},
+};
+use bytes::{Bytes, BytesMut};
 use http::uri::{InvalidUriBytes, Uri};
 use std::{
     convert::{TryFrom, TryInto},
     fmt,
+    net::IpAddr,
     sync::Arc,
     time::Duration,
 };
+use tower::{layer::Layer, util::BoxService, Service};
+use tower_make::MakeConnection;
 
 /// Channel builder.
 ///
@@ -20,6 +30,10 @@ use std::{
 pub struct Endpoint {
     pub(super) uri: Uri,
     pub(super) timeout: Option<Duration>,
+    pub(super) connect_timeout: Option<Duration>,
+    pub(super) tcp_keepalive: Option<Duration>,
+    pub(super) tcp_nodelay: bool,
+    pub(super) local_address: Option<IpAddr>,
     pub(super) concurrency_limit: Option<usize>,
     pub(super) rate_limit: Option<(u64, Duration)>,
     #[cfg(feature = "tls")]
@@ -29,6 +43,20 @@ pub struct Endpoint {
         Option<Arc<dyn Fn(&mut http::HeaderMap) + Send + Sync + 'static>>,
     pub(super) init_stream_window_size: Option<u32>,
     pub(super) init_connection_window_size: Option<u32>,
+    pub(super) keep_alive_interval: Option<Duration>,
+    pub(super) keep_alive_timeout: Option<Duration>,
+    pub(super) keep_alive_while_idle: bool,
+    pub(super) reconnect_backoff: Backoff,
+    pub(super) load: LoadKind,
+    pub(super) weight: u32,
+    pub(super) load_balancing_policy: LoadBalancingPolicy,
+    pub(super) retry_policy: Option<RetryPolicy>,
+    pub(super) hedging_policy: Option<HedgingPolicy>,
+    pub(super) send_compression: Option<CompressionEncoding>,
+    pub(super) accept_compression: EnabledCompressionEncodings,
+    pub(super) max_decompressed_size: Option<usize>,
+    pub(super) wait_for_ready: bool,
+    pub(super) layers: Vec<BoxLayer<http::Request<BoxBody>, http::Response<hyper::Body>>>,
 }
 
 impl Endpoint {
@@ -48,23 +76,34 @@ impl Endpoint {
 
     /// Convert an `Endpoint` from a static string.
     ///
+    /// Besides `http` and `https` URIs this accepts `unix:///path/to/socket`
+    /// to connect to a Unix domain socket.
+    ///
     /// ```
     /// # use tonic::transport::Endpoint;
     /// Endpoint::from_static("https://example.com");
+    /// Endpoint::from_static("unix:///tmp/example.sock");
     /// ```
     pub fn from_static(s: &'static str) -> Self {
-        let uri = Uri::from_static(s);
+        let uri = match uds_uri(s.as_bytes()) {
+            Some(uri) => Uri::from_shared(uri).expect("static str is not valid uri"),
+            None => Uri::from_static(s),
+        };
         Self::from(uri)
     }
 
     /// Convert an `Endpoint` from shared bytes.
     ///
+    /// Besides `http` and `https` URIs this accepts `unix:///path/to/socket`
+    /// to connect to a Unix domain socket.
+    ///
     /// ```
     /// # use tonic::transport::Endpoint;
     /// Endpoint::from_shared("https://example.com".to_string());
     /// ```
     pub fn from_shared(s: impl Into<Bytes>) -> Result<Self, InvalidUriBytes> {
-        let uri = Uri::from_shared(s.into())?;
+        let s = s.into();
+        let uri = Uri::from_shared(uds_uri(&s).unwrap_or(s))?;
         Ok(Self::from(uri))
     }
 
@@ -83,6 +122,67 @@ impl Endpoint {
         }
     }
 
+    /// Apply a timeout to establishing a connection, for the first one as
+    /// well as for reconnects.
+    ///
+    /// Unlike [`timeout`] this does not limit requests, only connecting to
+    /// the endpoint, which covers dialing it as well as the TLS and HTTP/2
+    /// handshakes. By default connecting waits for as long as the operating
+    /// system does, which can be minutes for an unreachable host.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # use std::time::Duration;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.connect_timeout(Duration::from_secs(5));
+    /// ```
+    ///
+    /// [`timeout`]: #method.timeout
+    pub fn connect_timeout(self, dur: Duration) -> Self {
+        Endpoint {
+            connect_timeout: Some(dur),
+            ..self
+        }
+    }
+
+    /// Set the `SO_KEEPALIVE` idle time of TCP connections to this endpoint.
+    ///
+    /// `None` disables TCP keepalive, which is the default. This is separate
+    /// from the HTTP/2 pings set up with [`keep_alive_interval`].
+    ///
+    /// [`keep_alive_interval`]: #method.keep_alive_interval
+    pub fn tcp_keepalive(self, keepalive: Option<Duration>) -> Self {
+        Endpoint {
+            tcp_keepalive: keepalive,
+            ..self
+        }
+    }
+
+    /// Set the `TCP_NODELAY` option of TCP connections to this endpoint.
+    ///
+    /// Default is `true`.
+    pub fn tcp_nodelay(self, enabled: bool) -> Self {
+        Endpoint {
+            tcp_nodelay: enabled,
+            ..self
+        }
+    }
+
+    /// Bind TCP connections to this endpoint to a local address before
+    /// connecting.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.local_address(Some("10.0.0.2".parse().unwrap()));
+    /// ```
+    pub fn local_address(self, addr: Option<IpAddr>) -> Self {
+        Endpoint {
+            local_address: addr,
+            ..self
+        }
+    }
+
     /// Apply a concurrency limit to each request.
     ///
     /// ```
@@ -135,6 +235,278 @@ impl Endpoint {
         }
     }
 
+    /// Set how often HTTP/2 PING frames are sent to check that the
+    /// connection is still alive.
+    ///
+    /// If the server does not answer a ping within [`keep_alive_timeout`]
+    /// the connection is closed and re-established. Default is to not send
+    /// pings. This requires the `keepalive` feature.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # use std::time::Duration;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.keep_alive_interval(Duration::from_secs(30));
+    /// ```
+    ///
+    /// [`keep_alive_timeout`]: #method.keep_alive_timeout
+    #[cfg(feature = "keepalive")]
+    pub fn keep_alive_interval(self, interval: Duration) -> Self {
+        Endpoint {
+            keep_alive_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Set how long to wait for the server to answer a keepalive ping before
+    /// closing the connection.
+    ///
+    /// Only used together with [`keep_alive_interval`]. Default is 20 seconds.
+    ///
+    /// [`keep_alive_interval`]: #method.keep_alive_interval
+    #[cfg(feature = "keepalive")]
+    pub fn keep_alive_timeout(self, timeout: Duration) -> Self {
+        Endpoint {
+            keep_alive_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Set whether keepalive pings are sent on a connection that had no
+    /// traffic since the previous ping.
+    ///
+    /// Servers commonly limit how often idle clients may ping, so this should
+    /// be combined with a generous [`keep_alive_interval`]. Default is `false`.
+    ///
+    /// [`keep_alive_interval`]: #method.keep_alive_interval
+    #[cfg(feature = "keepalive")]
+    pub fn keep_alive_while_idle(self, enabled: bool) -> Self {
+        Endpoint {
+            keep_alive_while_idle: enabled,
+            ..self
+        }
+    }
+
+    /// Set the delays between attempts to reconnect after a connection to
+    /// this endpoint was lost.
+    ///
+    /// After each failed attempt the delay grows by a factor of 1.6, up to
+    /// `max`, and every delay is randomly spread by 20% so that clients do
+    /// not reconnect in lockstep. While every connection of a channel backs
+    /// off, its requests fail with `Code::Unavailable` instead of waiting,
+    /// unless [`wait_for_ready`] is set.
+    /// Default is 1 second growing up to 120 seconds.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # use std::time::Duration;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.reconnect_backoff(Duration::from_millis(100), Duration::from_secs(10));
+    /// ```
+    ///
+    /// [`wait_for_ready`]: #method.wait_for_ready
+    pub fn reconnect_backoff(self, initial: Duration, max: Duration) -> Self {
+        Endpoint {
+            reconnect_backoff: Backoff::new(initial, max),
+            ..self
+        }
+    }
+
+    /// Measure the load of connections to this endpoint by the number of
+    /// requests in flight on them.
+    ///
+    /// This is what balanced channels use to pick between endpoints by
+    /// default.
+    pub fn load_pending_requests(self) -> Self {
+        Endpoint {
+            load: LoadKind::PendingRequests,
+            ..self
+        }
+    }
+
+    /// Measure the load of connections to this endpoint by a peak-EWMA of
+    /// their response latency, multiplied by the number of requests in flight.
+    ///
+    /// The estimate jumps up with every response slower than it, and decays
+    /// towards faster responses over `decay`. `default_rtt` is assumed until
+    /// the first response arrives. This steers a balanced channel away from
+    /// slow endpoints, not just busy ones.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # use std::time::Duration;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.load_peak_ewma(Duration::from_millis(30), Duration::from_secs(10));
+    /// ```
+    pub fn load_peak_ewma(self, default_rtt: Duration, decay: Duration) -> Self {
+        Endpoint {
+            load: LoadKind::PeakEwma { default_rtt, decay },
+            ..self
+        }
+    }
+
+    /// Set the relative share of requests this endpoint gets when balanced
+    /// with [`LoadBalancingPolicy::WeightedRoundRobin`].
+    ///
+    /// An endpoint with weight `3` gets three times as many requests as one
+    /// with weight `1`. Endpoints with weight `0` are only used when no other
+    /// endpoint is ready. Default is `1`.
+    ///
+    /// [`LoadBalancingPolicy::WeightedRoundRobin`]: channel/enum.LoadBalancingPolicy.html#variant.WeightedRoundRobin
+    pub fn weight(self, weight: u32) -> Self {
+        Endpoint { weight, ..self }
+    }
+
+    /// Set the policy a balanced channel uses to pick between endpoints.
+    ///
+    /// When balancing a list of endpoints, the policy of the first one is
+    /// used for the whole channel.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # use tonic::transport::channel::LoadBalancingPolicy;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.load_balancing_policy(LoadBalancingPolicy::PickFirst);
+    /// ```
+    pub fn load_balancing_policy(self, policy: LoadBalancingPolicy) -> Self {
+        Endpoint {
+            load_balancing_policy: policy,
+            ..self
+        }
+    }
+
+    /// Retry failed requests following `policy`.
+    ///
+    /// When balancing a list of endpoints, the policy of the first one is
+    /// used for the whole channel. By default requests are not retried.
+    /// This replaces any [`hedging_policy`], as a channel does one or the
+    /// other.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # use tonic::transport::channel::RetryPolicy;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.retry_policy(RetryPolicy::new().max_attempts(4));
+    /// ```
+    ///
+    /// [`hedging_policy`]: #method.hedging_policy
+    pub fn retry_policy(self, policy: RetryPolicy) -> Self {
+        Endpoint {
+            retry_policy: Some(policy),
+            hedging_policy: None,
+            ..self
+        }
+    }
+
+    /// Hedge unary requests following `policy`, sending them to several
+    /// endpoints of a balanced channel and taking the first response.
+    ///
+    /// When balancing a list of endpoints, the policy of the first one is
+    /// used for the whole channel. This replaces any [`retry_policy`].
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # use tonic::transport::channel::HedgingPolicy;
+    /// # use std::time::Duration;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.hedging_policy(HedgingPolicy::new().hedging_delay(Duration::from_millis(10)));
+    /// ```
+    ///
+    /// [`retry_policy`]: #method.retry_policy
+    pub fn hedging_policy(self, policy: HedgingPolicy) -> Self {
+        Endpoint {
+            hedging_policy: Some(policy),
+            retry_policy: None,
+            ..self
+        }
+    }
+
+    /// Compress the requests of every client using the channel with `gzip`.
+    ///
+    /// Requires the server to accept `gzip` otherwise it might respond with an
+    /// error.
+    pub fn send_gzip(self) -> Self {
+        self.send_compressed(CompressionEncoding::gzip())
+    }
+
+    /// Enable accepting `gzip` compressed responses on every client using
+    /// the channel.
+    pub fn accept_gzip(self) -> Self {
+        self.accept_compressed(CompressionEncoding::gzip())
+    }
+
+    /// Compress the requests of every client using the channel with
+    /// `encoding`.
+    ///
+    /// When balancing a list of endpoints, the setting of the first one is
+    /// used for the whole channel. A client that picks its own encoding with
+    /// [`Grpc::send_compressed`] takes precedence.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # use tonic::codec::CompressionEncoding;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.send_compressed(CompressionEncoding::gzip());
+    /// ```
+    ///
+    /// [`Grpc::send_compressed`]: ../client/struct.Grpc.html#method.send_compressed
+    pub fn send_compressed(self, encoding: CompressionEncoding) -> Self {
+        Endpoint {
+            send_compression: Some(encoding),
+            ..self
+        }
+    }
+
+    /// Enable accepting responses compressed with `encoding` on every client
+    /// using the channel.
+    ///
+    /// These are advertised after the encodings a client enables itself.
+    /// When balancing a list of endpoints, the setting of the first one is
+    /// used for the whole channel.
+    pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+        self.accept_compression.enable(encoding);
+        self
+    }
+
+    /// Reject compressed responses that decompress to more than `limit`
+    /// bytes with `Code::ResourceExhausted`.
+    ///
+    /// Uncompressed messages are not limited. By default a message may
+    /// decompress to as much as an uncompressed one can hold, 4 GiB.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.max_decompressed_message_size(64 * 1024 * 1024);
+    /// ```
+    pub fn max_decompressed_message_size(self, limit: usize) -> Self {
+        Endpoint {
+            max_decompressed_size: Some(limit),
+            ..self
+        }
+    }
+
+    /// Set whether requests wait for a connection while the channel has
+    /// none to send on.
+    ///
+    /// By default a channel fails fast: while every one of its connections
+    /// backs off after a failed attempt, requests fail right away with
+    /// `Code::Unavailable`. With this enabled they are held until a
+    /// connection is established instead, however long that takes. This
+    /// applies to the whole channel. Default is `false`.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # let mut builder = Endpoint::from_static("https://example.com");
+    /// builder.wait_for_ready(true);
+    /// ```
+    pub fn wait_for_ready(self, enabled: bool) -> Self {
+        Endpoint {
+            wait_for_ready: enabled,
+            ..self
+        }
+    }
+
     /// Intercept outbound HTTP Request headers;
     pub fn intercept_headers<F>(self, f: F) -> Self
     where
@@ -146,6 +518,39 @@ impl Endpoint {
         }
     }
 
+    /// Wrap each connection of the channel in a [`tower::Layer`].
+    ///
+    /// Layers can be added repeatedly, the first one added being the
+    /// outermost. They wrap the timeout, concurrency limit and rate limit of
+    /// the connection, and see requests after the channel picked the
+    /// connection to send them on.
+    ///
+    /// ```
+    /// # use tonic::transport::Endpoint;
+    /// # use std::time::Duration;
+    /// use tower::timeout::TimeoutLayer;
+    ///
+    /// Endpoint::from_static("https://example.com")
+    ///     .layer(TimeoutLayer::new(Duration::from_secs(30)));
+    /// ```
+    ///
+    /// [`tower::Layer`]: https://docs.rs/tower/0.3.0-alpha.2/tower/trait.Layer.html
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxService<http::Request<BoxBody>, http::Response<hyper::Body>, crate::Error>>
+            + Send
+            + Sync
+            + 'static,
+        L::Service: Service<http::Request<BoxBody>, Response = http::Response<hyper::Body>>
+            + Send
+            + 'static,
+        <L::Service as Service<http::Request<BoxBody>>>::Future: Send + 'static,
+        <L::Service as Service<http::Request<BoxBody>>>::Error: Into<crate::Error>,
+    {
+        self.layers.push(box_layer(layer));
+        self
+    }
+
     /// Configures TLS for the endpoint.
     #[cfg(feature = "tls")]
     pub fn tls_config(self, tls_config: ClientTlsConfig) -> Self {
@@ -157,10 +562,62 @@ impl Endpoint {
 
     /// Create a channel from this config.
     pub async fn connect(&self) -> Result<Channel, super::Error> {
-        Channel::connect(self.clone()).await
+        Channel::connect(DefaultConnector::new(self), self.clone()).await
+    }
+
+    /// Create a channel from this config without waiting for the connection.
+    ///
+    /// The channel is returned right away and connects when it is first
+    /// used. Requests wait while the connection is being established. Once
+    /// an attempt failed they fail with `Code::Unavailable` until a later
+    /// one succeeds, unless [`wait_for_ready`] is set, in which case they
+    /// wait for a server that is not up yet to start.
+    ///
+    /// ```no_run
+    /// # use tonic::transport::Endpoint;
+    /// let channel = Endpoint::from_static("http://[::1]:50051").connect_lazy();
+    /// ```
+    ///
+    /// [`wait_for_ready`]: #method.wait_for_ready
+    pub fn connect_lazy(&self) -> Channel {
+        Channel::new(DefaultConnector::new(self), self.clone())
+    }
+
+    /// Create a channel from this config, establishing connections with the
+    /// provided connector instead of dialing TCP.
+    ///
+    /// Any `tower::Service<Uri>` whose response implements `AsyncRead` and
+    /// `AsyncWrite` can be used, for example to go through a proxy or to
+    /// connect to an in-memory transport. TLS, timeouts and limits configured
+    /// on this endpoint, including the connect timeout, are still applied on
+    /// top, while the TCP options are left to the connector.
+    pub async fn connect_with_connector<C>(&self, connector: C) -> Result<Channel, super::Error>
+    where
+        C: MakeConnection<Uri> + Send + 'static,
+        C::Connection: Unpin + Send + 'static,
+        C::Future: Send + 'static,
+        C::Error: Into<crate::Error> + Send,
+    {
+        Channel::connect(connector, self.clone()).await
     }
 }
 
+/// `http::Uri` refuses a scheme without an authority, so `unix:///path` and
+/// `unix:/path` are rewritten to `unix://localhost/path`.
+fn uds_uri(s: &[u8]) -> Option<Bytes> {
+    let path = if s.starts_with(b"unix:///") {
+        &s[7..]
+    } else if s.starts_with(b"unix:/") && !s.starts_with(b"unix://") {
+        &s[5..]
+    } else {
+        return None;
+    };
+
+    let mut uri = BytesMut::from(&b"unix://localhost"[..]);
+    uri.extend_from_slice(path);
+    Some(uri.freeze())
+}
+
 impl From<Uri> for Endpoint {
     fn from(uri: Uri) -> Self {
         Self {
@@ -168,12 +625,30 @@ impl From<Uri> for Endpoint {
             concurrency_limit: None,
             rate_limit: None,
             timeout: None,
+            connect_timeout: None,
+            tcp_keepalive: None,
+            tcp_nodelay: true,
+            local_address: None,
             #[cfg(feature = "tls")]
             tls: None,
             buffer_size: None,
             interceptor_headers: None,
             init_stream_window_size: None,
             init_connection_window_size: None,
+            keep_alive_interval: None,
+            keep_alive_timeout: None,
+            keep_alive_while_idle: false,
+            reconnect_backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(120)),
+            load: LoadKind::default(),
+            weight: 1,
+            load_balancing_policy: LoadBalancingPolicy::default(),
+            retry_policy: None,
+            hedging_policy: None,
+            send_compression: None,
+            accept_compression: EnabledCompressionEncodings::default(),
+            max_decompressed_size: None,
+            wait_for_ready: false,
+            layers: Vec::new(),
         }
     }
 }
@@ -354,3 +829,84 @@ impl ClientTlsConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_uds_uris() {
+        for s in &["unix:///tmp/example.sock", "unix:/tmp/example.sock"] {
+            let endpoint = Endpoint::from_shared(s.to_string()).unwrap();
+            assert_eq!(endpoint.uri.scheme_str(), Some("unix"));
+            assert_eq!(endpoint.uri.path(), "/tmp/example.sock");
+        }
+
+        let endpoint = Endpoint::from_static("https://example.com");
+        assert_eq!(endpoint.uri.host(), Some("example.com"));
+
+        let endpoint = Channel::from_static("unix:///tmp/example.sock");
+        assert_eq!(endpoint.uri.scheme_str(), Some("unix"));
+        let endpoint = Channel::from_shared("unix:/tmp/example.sock").unwrap();
+        assert_eq!(endpoint.uri.path(), "/tmp/example.sock");
+    }
+
+    #[tokio::test]
+    async fn channel_carries_compression() {
+        use crate::client::GrpcService;
+
+        let channel = Endpoint::from_static("http://127.0.0.1:1")
+            .send_gzip()
+            .accept_compressed(CompressionEncoding::deflate())
+            .max_decompressed_message_size(1024)
+            .connect_lazy();
+
+        assert_eq!(channel.max_decompressed_message_size(), Some(1024));
+
+        assert_eq!(
+            channel.send_compression_encoding(),
+            Some(CompressionEncoding::gzip())
+        );
+        assert!(channel
+            .accept_compression_encodings()
+            .is_enabled(&CompressionEncoding::deflate()));
+        assert!(!channel
+            .accept_compression_encodings()
+            .is_enabled(&CompressionEncoding::gzip()));
+    }
+
+    #[tokio::test]
+    async fn balance_channel_takes_template_settings() {
+        use crate::client::GrpcService;
+
+        let template = Endpoint::from_static("http://127.0.0.1:1")
+            .send_gzip()
+            .max_decompressed_message_size(1024);
+        let (channel, _tx) = Channel::balance_channel::<&str>(template, 1);
+
+        assert_eq!(channel.max_decompressed_message_size(), Some(1024));
+        assert_eq!(
+            channel.send_compression_encoding(),
+            Some(CompressionEncoding::gzip())
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_timeout_bounds_unroutable_addresses() {
+        use std::time::Instant;
+        use tokio::timer::Timeout;
+
+        // Nothing answers on this address, dialing it hangs until the timeout
+        // unless the network reports it unreachable first.
+        let endpoint = Endpoint::from_static("http://10.255.255.1:80")
+            .connect_timeout(Duration::from_millis(100));
+
+        let start = Instant::now();
+        let res = Timeout::new(endpoint.connect(), Duration::from_secs(5))
+            .await
+            .expect("connecting outlived the connect timeout");
+
+        assert!(res.is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
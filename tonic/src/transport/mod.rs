@@ -93,6 +93,7 @@
 //! [rustls]: https://docs.rs/rustls/0.16.0/rustls/
 
 pub mod channel;
+pub mod dns;
 pub mod server;
 
 mod endpoint;
@@ -1,15 +1,22 @@
 //! Server implementation and builder.
 
-use super::service::{layer_fn, BoxedIo, Or, Routes, ServiceBuilderExt};
+use super::service::{
+    apply_layers, box_layer, in_process, layer_fn, BoxLayer, BoxRoute, BoxedIo, GrpcTimeoutLayer,
+    KeepAlive, Routes, ServerIo, ServiceBuilderExt,
+};
 #[cfg(feature = "tls")]
 use super::{
     service::TlsAcceptor,
     tls::{Identity, TlsProvider},
     Certificate,
 };
-use crate::body::BoxBody;
+use super::{Channel, Endpoint};
+use crate::{
+    body::BoxBody,
+    codec::{CompressionConfig, CompressionEncoding},
+};
 use futures_core::Stream;
-use futures_util::{future, ready, try_future::MapErr, TryFutureExt, TryStreamExt};
+use futures_util::{future, pin_mut, ready, try_future::MapErr, TryFutureExt, TryStreamExt};
 use http::{Request, Response};
 use hyper::{
     server::{accept::Accept, conn},
@@ -22,17 +29,22 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
-    // time::Duration,
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::{oneshot, watch},
+    timer::delay_for,
 };
 use tower::{
     layer::{util::Stack, Layer},
     limit::concurrency::ConcurrencyLimitLayer,
-    // timeout::TimeoutLayer,
-    Service,
-    ServiceBuilder,
+    Service, ServiceBuilder,
 };
-#[cfg(feature = "tls")]
-use tracing::error;
+use tracing::{debug, error};
 
 type BoxService = tower::util::BoxService<Request<Body>, Response<BoxBody>, crate::Error>;
 type Interceptor = Arc<dyn Layer<BoxService, Service = BoxService> + Send + Sync + 'static>;
@@ -48,20 +60,128 @@ type Interceptor = Arc<dyn Layer<BoxService, Service = BoxService> + Send + Sync
 #[derive(Default, Clone)]
 pub struct Server {
     interceptor: Option<Interceptor>,
+    request_interceptor: Option<crate::Interceptor>,
+    layers: Vec<BoxLayer<Request<Body>, Response<BoxBody>>>,
     concurrency_limit: Option<usize>,
-    // timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    drain_timeout: Option<Duration>,
+    keep_alive_interval: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+    keep_alive_while_idle: Option<bool>,
     #[cfg(feature = "tls")]
     tls: Option<TlsAcceptor>,
     init_stream_window_size: Option<u32>,
     init_connection_window_size: Option<u32>,
     max_concurrent_streams: Option<u32>,
+    compression: CompressionConfig,
+}
+
+/// Connections a [`Server`] can serve on with
+/// [`Router::serve_with_connected_incoming`].
+///
+/// This exposes the address of the peer to request handlers through
+/// [`Request::remote_addr`]. Connection types that have no meaningful
+/// address can rely on the default implementation:
+///
+/// ```
+/// # use tonic::transport::server::Connected;
+/// struct Pipe;
+///
+/// impl Connected for Pipe {}
+/// ```
+///
+/// [`Server`]: struct.Server.html
+/// [`Router::serve_with_connected_incoming`]: struct.Router.html#method.serve_with_connected_incoming
+/// [`Request::remote_addr`]: ../../struct.Request.html#method.remote_addr
+pub trait Connected {
+    /// The address of the peer of this connection, if it has one.
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
 }
 
-/// A stack based `Service` router.
+impl Connected for conn::AddrStream {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(conn::AddrStream::remote_addr(self))
+    }
+}
+
+impl Connected for TcpStream {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr().ok()
+    }
+}
+
+#[cfg(unix)]
+impl Connected for UnixStream {}
+
+/// What is known about the peer of a connection, handed to request handlers
+/// through the request extensions.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectInfo {
+    pub(crate) remote_addr: Option<SocketAddr>,
+    #[cfg(feature = "tls")]
+    pub(crate) peer_certs: Option<Arc<Vec<Certificate>>>,
+}
+
+/// A `Service` router.
+///
+/// Requests are dispatched on the `Service-Name` in their path, which must
+/// match the [`ServiceName`] of a service exactly. Requests for services
+/// that were not added are answered with `Code::Unimplemented`.
+///
+/// [`ServiceName`]: trait.ServiceName.html
 #[derive(Debug)]
-pub struct Router<A, B> {
+pub struct Router {
     server: Server,
-    routes: Routes<A, B, Request<Body>>,
+    routes: Routes,
+}
+
+/// A service that can be added to a [`Router`].
+///
+/// This erases the type of the service, so that services of different types
+/// can be collected, for example into a `Vec` that is assembled at runtime,
+/// and added at once with [`Router::add_routes`].
+///
+/// ```
+/// # use tonic::transport::{server::Route, Server};
+/// # fn docs(services: Vec<Route>) {
+/// Server::builder().add_routes(services);
+/// # }
+/// ```
+///
+/// [`Router`]: struct.Router.html
+/// [`Router::add_routes`]: struct.Router.html#method.add_routes
+pub struct Route {
+    name: &'static str,
+    svc: BoxRoute,
+}
+
+impl Route {
+    /// Create a route to `svc`, for requests to its [`ServiceName`].
+    ///
+    /// [`ServiceName`]: trait.ServiceName.html
+    pub fn new<S>(svc: S) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>>
+            + ServiceName
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<crate::Error> + Send,
+    {
+        Route {
+            name: S::NAME,
+            svc: BoxRoute::new(svc),
+        }
+    }
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route").field("name", &self.name).finish()
+    }
 }
 
 /// A trait to provide a static reference to the service's
@@ -105,12 +225,96 @@ impl Server {
         }
     }
 
-    // FIXME: tower-timeout currentlly uses `From` instead of `Into` for the error
-    // so our services do not align.
-    // pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
-    //     self.timeout = Some(timeout);
-    //     self
-    // }
+    /// Set a timeout for all request handlers.
+    ///
+    /// A request whose handler has not produced a response within `timeout`
+    /// is answered with a `DeadlineExceeded` status.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let mut builder = Server::builder();
+    /// builder.timeout(Duration::from_secs(30));
+    /// ```
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Server {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Set how long in-flight requests may keep running after a graceful
+    /// shutdown has been triggered.
+    ///
+    /// Once this elapses the connections that are still open are closed,
+    /// failing their in-flight requests, and the server future returns.
+    /// Default is to wait for all of them to finish.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let mut builder = Server::builder();
+    /// builder.drain_timeout(Duration::from_secs(10));
+    /// ```
+    pub fn drain_timeout(self, timeout: Duration) -> Self {
+        Server {
+            drain_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Set how often HTTP/2 PING frames are sent on every open connection.
+    ///
+    /// A connection whose client does not answer a ping within
+    /// [`keep_alive_timeout`] is closed. Default is to not send pings. This
+    /// requires the `keepalive` feature.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let mut builder = Server::builder();
+    /// builder.keep_alive_interval(Duration::from_secs(60));
+    /// ```
+    ///
+    /// [`keep_alive_timeout`]: #method.keep_alive_timeout
+    #[cfg(feature = "keepalive")]
+    pub fn keep_alive_interval(self, interval: Duration) -> Self {
+        Server {
+            keep_alive_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Set how long to wait for a client to answer a keepalive ping before
+    /// closing the connection.
+    ///
+    /// Only used together with [`keep_alive_interval`]. Default is 20 seconds.
+    ///
+    /// [`keep_alive_interval`]: #method.keep_alive_interval
+    #[cfg(feature = "keepalive")]
+    pub fn keep_alive_timeout(self, timeout: Duration) -> Self {
+        Server {
+            keep_alive_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Set whether keepalive pings are sent on a connection that had no
+    /// traffic since the previous ping.
+    ///
+    /// Pinging idle connections is how a server finds clients that went away
+    /// without closing them, so this is `true` by default. Turning it off
+    /// only checks connections with requests in flight. Only used together
+    /// with [`keep_alive_interval`].
+    ///
+    /// [`keep_alive_interval`]: #method.keep_alive_interval
+    #[cfg(feature = "keepalive")]
+    pub fn keep_alive_while_idle(self, enabled: bool) -> Self {
+        Server {
+            keep_alive_while_idle: Some(enabled),
+            ..self
+        }
+    }
 
     /// Sets the [`SETTINGS_INITIAL_WINDOW_SIZE`][spec] option for HTTP2
     /// stream-level flow control.
@@ -148,6 +352,78 @@ impl Server {
         }
     }
 
+    /// Enable accepting `gzip` compressed requests on all services.
+    ///
+    /// This applies to every service served by this server in addition to
+    /// whatever the services enable themselves.
+    pub fn accept_gzip(self) -> Self {
+        self.accept_compressed(CompressionEncoding::gzip())
+    }
+
+    /// Enable sending `gzip` compressed responses on all services.
+    ///
+    /// Responses are only compressed when the client advertises `gzip` through
+    /// the `grpc-accept-encoding` header.
+    pub fn send_gzip(self) -> Self {
+        self.send_compressed(CompressionEncoding::gzip())
+    }
+
+    /// Enable accepting requests compressed with `encoding` on all services.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use tonic::codec::CompressionEncoding;
+    /// # let mut builder = Server::builder();
+    /// builder
+    ///     .accept_compressed(CompressionEncoding::gzip())
+    ///     .accept_compressed(CompressionEncoding::deflate());
+    /// ```
+    pub fn accept_compressed(self, encoding: CompressionEncoding) -> Self {
+        let mut compression = self.compression;
+        compression.accept.enable(encoding);
+
+        Server {
+            compression,
+            ..self
+        }
+    }
+
+    /// Enable sending responses compressed with `encoding` on all services.
+    ///
+    /// The encoding is only used when the client advertises it through the
+    /// `grpc-accept-encoding` header.
+    pub fn send_compressed(self, encoding: CompressionEncoding) -> Self {
+        let mut compression = self.compression;
+        compression.send.enable(encoding);
+
+        Server {
+            compression,
+            ..self
+        }
+    }
+
+    /// Reject compressed requests that decompress to more than `limit`
+    /// bytes with `Code::ResourceExhausted`.
+    ///
+    /// This protects services from messages crafted to expand to huge sizes.
+    /// Uncompressed messages are not limited. By default a message may
+    /// decompress to as much as an uncompressed one can hold, 4 GiB.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # let mut builder = Server::builder();
+    /// builder.max_decompressed_message_size(64 * 1024 * 1024);
+    /// ```
+    pub fn max_decompressed_message_size(self, limit: usize) -> Self {
+        let mut compression = self.compression;
+        compression.max_decompressed_size = Some(limit);
+
+        Server {
+            compression,
+            ..self
+        }
+    }
+
     /// Intercept the execution of gRPC methods.
     ///
     /// ```
@@ -177,11 +453,64 @@ impl Server {
         }
     }
 
+    /// Run the requests of all services through `interceptor`.
+    ///
+    /// This runs before any interceptor of the individual services. A request
+    /// the interceptor rejects is answered with the returned [`Status`]
+    /// without calling the service.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use tonic::{Code, Request, Status};
+    /// # let builder = Server::builder();
+    /// builder.interceptor(|request: Request<()>| {
+    ///     if request.metadata().get("x-tenant").is_some() {
+    ///         Ok(request)
+    ///     } else {
+    ///         Err(Status::new(Code::InvalidArgument, "missing tenant"))
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`Status`]: ../struct.Status.html
+    pub fn interceptor(self, interceptor: impl Into<crate::Interceptor>) -> Self {
+        Server {
+            request_interceptor: Some(interceptor.into()),
+            ..self
+        }
+    }
+
+    /// Wrap all services in a [`tower::Layer`].
+    ///
+    /// Layers can be added repeatedly, the first one added being the
+    /// outermost. They wrap the concurrency limit and timeout of the server,
+    /// and see the requests before they are routed to a service.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// use tower::timeout::TimeoutLayer;
+    ///
+    /// Server::builder().layer(TimeoutLayer::new(Duration::from_secs(30)));
+    /// ```
+    ///
+    /// [`tower::Layer`]: https://docs.rs/tower/0.3.0-alpha.2/tower/trait.Layer.html
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxService> + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<BoxBody>> + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<crate::Error>,
+    {
+        self.layers.push(box_layer(layer));
+        self
+    }
+
     /// Create a router with the `S` typed service as the first service.
     ///
     /// This will clone the `Server` builder and create a router that will
     /// route around different services.
-    pub fn add_service<S>(&mut self, svc: S) -> Router<S, Unimplemented>
+    pub fn add_service<S>(&mut self, svc: S) -> Router
     where
         S: Service<Request<Body>, Response = Response<BoxBody>>
             + ServiceName
@@ -191,101 +520,159 @@ impl Server {
         S::Future: Send + 'static,
         S::Error: Into<crate::Error> + Send,
     {
-        Router::new(self.clone(), svc)
+        Router::new(self.clone()).add_service(svc)
     }
 
-    pub(crate) async fn serve<S>(self, addr: SocketAddr, svc: S) -> Result<(), super::Error>
+    /// Create a router with the given routes.
+    ///
+    /// This will clone the `Server` builder and create a router that will
+    /// route around the services of `routes`.
+    pub fn add_routes(&mut self, routes: impl IntoIterator<Item = Route>) -> Router {
+        Router::new(self.clone()).add_routes(routes)
+    }
+
+    pub(crate) async fn serve<S, I, IO, IE, F>(
+        self,
+        svc: S,
+        incoming: I,
+        remote_addr: fn(&IO) -> Option<SocketAddr>,
+        signal: Option<F>,
+    ) -> Result<(), super::Error>
     where
         S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
         S::Future: Send + 'static,
         S::Error: Into<crate::Error> + Send,
+        I: Stream<Item = Result<IO, IE>>,
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        IE: Into<crate::Error>,
+        F: Future<Output = ()>,
     {
         let interceptor = self.interceptor.clone();
+        let request_interceptor = self.request_interceptor.clone();
+        let layers = self.layers.clone();
         let concurrency_limit = self.concurrency_limit;
         let init_connection_window_size = self.init_connection_window_size;
         let init_stream_window_size = self.init_stream_window_size;
         let max_concurrent_streams = self.max_concurrent_streams;
-        // let timeout = self.timeout.clone();
+        let compression = self.compression.clone();
+        let timeout = self.timeout;
+        let drain_timeout = self.drain_timeout;
+        let keep_alive = KeepAlive {
+            interval: self.keep_alive_interval,
+            timeout: self.keep_alive_timeout,
+            while_idle: self.keep_alive_while_idle.unwrap_or(true),
+        };
 
+        // Broadcast on once the drain timeout elapses, to close connections
+        // that are still open.
+        let (close_tx, close_rx) = watch::channel(());
+
+        let incoming = incoming.map_err(Into::<crate::Error>::into);
         let incoming = hyper::server::accept::from_stream(async_stream::try_stream! {
-            let mut tcp = TcpIncoming::bind(addr)?;
+            pin_mut!(incoming);
+
+            while let Some(stream) = incoming.try_next().await? {
+                #[allow(unused_mut)]
+                let mut info = ConnectInfo {
+                    remote_addr: remote_addr(&stream),
+                    ..ConnectInfo::default()
+                };
 
-            while let Some(stream) = tcp.try_next().await? {
                 #[cfg(feature = "tls")]
                 {
                     if let Some(tls) = &self.tls {
-                        let io = match tls.connect(stream.into_inner()).await {
-                            Ok(io) => io,
+                        let (io, certs) = match tls.connect(BoxedIo::new(stream)).await {
+                            Ok(res) => res,
                             Err(error) => {
                                 error!(message = "Unable to accept incoming connection.", %error);
                                 continue
                             },
                         };
-                        yield BoxedIo::new(io);
+                        info.peer_certs = certs.map(Arc::new);
+                        yield ServerIo::new(keep_alive.server(io), info, close_rx.clone());
                         continue;
                     }
                 }
 
-                yield BoxedIo::new(stream);
+                yield ServerIo::new(
+                    keep_alive.server(BoxedIo::new(stream)),
+                    info,
+                    close_rx.clone(),
+                );
             }
         });
 
         let svc = MakeSvc {
             inner: svc,
             interceptor,
+            request_interceptor,
+            layers,
             concurrency_limit,
-            // timeout,
+            compression,
+            timeout,
         };
 
-        hyper::Server::builder(incoming)
+        let server = hyper::Server::builder(incoming)
             .http2_only(true)
             .http2_initial_connection_window_size(init_connection_window_size)
             .http2_initial_stream_window_size(init_stream_window_size)
             .http2_max_concurrent_streams(max_concurrent_streams)
-            .serve(svc)
-            .await
-            .map_err(map_err)?;
+            .serve(svc);
 
-        Ok(())
-    }
-}
+        let signal = match signal {
+            Some(signal) => signal,
+            None => return server.await.map_err(map_err),
+        };
 
-impl<S> Router<S, Unimplemented> {
-    pub(crate) fn new(server: Server, svc: S) -> Self
-    where
-        S: Service<Request<Body>, Response = Response<BoxBody>>
-            + ServiceName
-            + Clone
-            + Send
-            + 'static,
-        S::Future: Send + 'static,
-        S::Error: Into<crate::Error> + Send,
-    {
-        let svc_name = <S as ServiceName>::NAME;
-        let svc_route = format!("/{}", svc_name);
-        let pred = move |req: &Request<Body>| {
-            let path = req.uri().path();
+        // Hyper stops accepting and sends a GOAWAY on every open connection
+        // once the signal fires, then waits for the connections to finish.
+        let (drain_tx, drain_rx) = oneshot::channel();
+        let signal = async move {
+            signal.await;
+            let _ = drain_tx.send(());
+        };
+        let server = server.with_graceful_shutdown(signal);
 
-            path.starts_with(&svc_route)
+        let drain_timeout = match drain_timeout {
+            Some(drain_timeout) => drain_timeout,
+            None => return server.await.map_err(map_err),
         };
+
+        let drain_expired = async move {
+            match drain_rx.await {
+                Ok(()) => delay_for(drain_timeout).await,
+                // The server finished without the signal ever firing.
+                Err(_) => future::pending().await,
+            }
+        };
+
+        pin_mut!(server, drain_expired);
+        match future::select(server, drain_expired).await {
+            future::Either::Left((result, _)) => result.map_err(map_err),
+            future::Either::Right(((), _)) => {
+                debug!("drain timeout elapsed, closing open connections");
+                let _ = close_tx.broadcast(());
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Router {
+    pub(crate) fn new(server: Server) -> Self {
         Self {
             server,
-            routes: Routes::new(pred, svc, Unimplemented::default()),
+            routes: Routes::default(),
         }
     }
-}
 
-impl<A, B> Router<A, B>
-where
-    A: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
-    A::Future: Send + 'static,
-    A::Error: Into<crate::Error> + Send,
-    B: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
-    B::Future: Send + 'static,
-    B::Error: Into<crate::Error> + Send,
-{
     /// Add a new service to this router.
-    pub fn add_service<S>(self, svc: S) -> Router<S, Or<A, B, Request<Body>>>
+    ///
+    /// A service added under the same [`ServiceName`] as an earlier one
+    /// replaces it.
+    ///
+    /// [`ServiceName`]: trait.ServiceName.html
+    pub fn add_service<S>(self, svc: S) -> Self
     where
         S: Service<Request<Body>, Response = Response<BoxBody>>
             + ServiceName
@@ -295,18 +682,16 @@ where
         S::Future: Send + 'static,
         S::Error: Into<crate::Error> + Send,
     {
-        let Self { routes, server } = self;
-
-        let svc_name = <S as ServiceName>::NAME;
-        let svc_route = format!("/{}", svc_name);
-        let pred = move |req: &Request<Body>| {
-            let path = req.uri().path();
+        self.add_routes(Some(Route::new(svc)))
+    }
 
-            path.starts_with(&svc_route)
-        };
-        let routes = routes.push(pred, svc);
+    /// Add the services of `routes` to this router.
+    pub fn add_routes(mut self, routes: impl IntoIterator<Item = Route>) -> Self {
+        for route in routes {
+            self.routes.insert(route.name.to_string(), route.svc);
+        }
 
-        Router { server, routes }
+        self
     }
 
     /// Consume this [`Server`] creating a future that will execute the server
@@ -314,7 +699,178 @@ where
     ///
     /// [`Server`]: struct.Server.html
     pub async fn serve(self, addr: SocketAddr) -> Result<(), super::Error> {
-        self.server.serve(addr, self.routes).await
+        let incoming = TcpIncoming::bind(addr).map_err(map_err)?;
+        self.server
+            .serve::<_, _, _, _, future::Ready<()>>(
+                self.routes,
+                incoming,
+                Connected::remote_addr,
+                None,
+            )
+            .await
+    }
+
+    /// Consume this [`Server`] creating a future that will execute the server
+    /// on [`tokio`]'s default executor and shut down gracefully once `signal`
+    /// completes.
+    ///
+    /// On shutdown the server stops accepting new connections and asks the
+    /// open ones to wind down, letting in-flight requests finish within the
+    /// configured [`drain_timeout`].
+    ///
+    /// [`Server`]: struct.Server.html
+    /// [`drain_timeout`]: struct.Server.html#method.drain_timeout
+    pub async fn serve_with_shutdown<F>(
+        self,
+        addr: SocketAddr,
+        signal: F,
+    ) -> Result<(), super::Error>
+    where
+        F: Future<Output = ()>,
+    {
+        let incoming = TcpIncoming::bind(addr).map_err(map_err)?;
+        self.server
+            .serve(self.routes, incoming, Connected::remote_addr, Some(signal))
+            .await
+    }
+
+    /// Consume this [`Server`] creating a future that will execute the server
+    /// on the connections yielded by `incoming`.
+    ///
+    /// This can be used to serve on Unix domain sockets, listeners handed over
+    /// by a process supervisor or in-memory pipes. If TLS is configured it is
+    /// applied on top of each connection.
+    ///
+    /// The address of the peer is not known for these connections, see
+    /// [`serve_with_connected_incoming`] to expose it to request handlers.
+    ///
+    /// [`Server`]: struct.Server.html
+    /// [`serve_with_connected_incoming`]: #method.serve_with_connected_incoming
+    pub async fn serve_with_incoming<I, IO, IE>(self, incoming: I) -> Result<(), super::Error>
+    where
+        I: Stream<Item = Result<IO, IE>>,
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        IE: Into<crate::Error>,
+    {
+        self.server
+            .serve::<_, _, _, _, future::Ready<()>>(self.routes, incoming, |_| None, None)
+            .await
+    }
+
+    /// Consume this [`Server`] creating a future that will execute the server
+    /// on the connections yielded by `incoming` and shut down gracefully once
+    /// `signal` completes.
+    ///
+    /// See [`serve_with_incoming`] and [`serve_with_shutdown`].
+    ///
+    /// [`Server`]: struct.Server.html
+    /// [`serve_with_incoming`]: #method.serve_with_incoming
+    /// [`serve_with_shutdown`]: #method.serve_with_shutdown
+    pub async fn serve_with_incoming_shutdown<I, IO, IE, F>(
+        self,
+        incoming: I,
+        signal: F,
+    ) -> Result<(), super::Error>
+    where
+        I: Stream<Item = Result<IO, IE>>,
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        IE: Into<crate::Error>,
+        F: Future<Output = ()>,
+    {
+        self.server
+            .serve(self.routes, incoming, |_| None, Some(signal))
+            .await
+    }
+
+    /// Consume this [`Server`] creating a future that will execute the server
+    /// on the connections yielded by `incoming`, exposing the address of
+    /// each peer to request handlers through [`Request::remote_addr`].
+    ///
+    /// See [`serve_with_incoming`].
+    ///
+    /// [`Server`]: struct.Server.html
+    /// [`Request::remote_addr`]: ../../struct.Request.html#method.remote_addr
+    /// [`serve_with_incoming`]: #method.serve_with_incoming
+    pub async fn serve_with_connected_incoming<I, IO, IE>(
+        self,
+        incoming: I,
+    ) -> Result<(), super::Error>
+    where
+        I: Stream<Item = Result<IO, IE>>,
+        IO: Connected + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        IE: Into<crate::Error>,
+    {
+        self.server
+            .serve::<_, _, _, _, future::Ready<()>>(
+                self.routes,
+                incoming,
+                Connected::remote_addr,
+                None,
+            )
+            .await
+    }
+
+    /// Consume this [`Server`] creating a future that will execute the server
+    /// on the connections yielded by `incoming`, exposing the address of
+    /// each peer to request handlers, and shut down gracefully once `signal`
+    /// completes.
+    ///
+    /// See [`serve_with_connected_incoming`] and [`serve_with_shutdown`].
+    ///
+    /// [`Server`]: struct.Server.html
+    /// [`serve_with_connected_incoming`]: #method.serve_with_connected_incoming
+    /// [`serve_with_shutdown`]: #method.serve_with_shutdown
+    pub async fn serve_with_connected_incoming_shutdown<I, IO, IE, F>(
+        self,
+        incoming: I,
+        signal: F,
+    ) -> Result<(), super::Error>
+    where
+        I: Stream<Item = Result<IO, IE>>,
+        IO: Connected + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        IE: Into<crate::Error>,
+        F: Future<Output = ()>,
+    {
+        self.server
+            .serve(self.routes, incoming, Connected::remote_addr, Some(signal))
+            .await
+    }
+
+    /// Serve this router in-process and return a [`Channel`] connected to it.
+    ///
+    /// Connections are made over in-memory pipes instead of sockets, so no
+    /// port is bound. This makes it convenient to call service
+    /// implementations through generated clients in tests. The server runs on
+    /// the default executor until every channel connected to it has been
+    /// dropped. TLS is not used, even if it was configured on the server.
+    ///
+    /// [`Channel`]: ../channel/struct.Channel.html
+    pub async fn serve_in_process(self) -> Result<Channel, super::Error> {
+        #[allow(unused_mut)]
+        let Self { mut server, routes } = self;
+
+        #[cfg(feature = "tls")]
+        {
+            server.tls = None;
+        }
+
+        let (connector, incoming) = in_process();
+        let serve = server.serve::<_, _, _, _, future::Ready<()>>(
+            routes,
+            incoming,
+            Connected::remote_addr,
+            None,
+        );
+
+        tokio_executor::spawn(async move {
+            if let Err(error) = serve.await {
+                error!(message = "In-process server failed.", %error);
+            }
+        });
+
+        Endpoint::from_static("http://localhost")
+            .connect_with_connector(connector)
+            .await
     }
 }
 
@@ -466,7 +1022,12 @@ impl Stream for TcpIncoming {
 }
 
 #[derive(Debug)]
-struct Svc<S>(S);
+struct Svc<S> {
+    inner: S,
+    compression: CompressionConfig,
+    request_interceptor: Option<crate::Interceptor>,
+    info: ConnectInfo,
+}
 
 impl<S> Service<Request<Body>> for Svc<S>
 where
@@ -478,22 +1039,30 @@ where
     type Future = MapErr<S::Future, fn(S::Error) -> crate::Error>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.0.poll_ready(cx).map_err(Into::into)
+        self.inner.poll_ready(cx).map_err(Into::into)
     }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
-        self.0.call(req).map_err(|e| e.into())
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        req.extensions_mut().insert(self.compression.clone());
+        if let Some(interceptor) = &self.request_interceptor {
+            req.extensions_mut().insert(interceptor.clone());
+        }
+        req.extensions_mut().insert(self.info.clone());
+        self.inner.call(req).map_err(|e| e.into())
     }
 }
 
 struct MakeSvc<S> {
     interceptor: Option<Interceptor>,
+    request_interceptor: Option<crate::Interceptor>,
+    layers: Vec<BoxLayer<Request<Body>, Response<BoxBody>>>,
     concurrency_limit: Option<usize>,
-    // timeout: Option<Duration>,
+    compression: CompressionConfig,
+    timeout: Option<Duration>,
     inner: S,
 }
 
-impl<S, T> Service<T> for MakeSvc<S>
+impl<'a, S> Service<&'a ServerIo> for MakeSvc<S>
 where
     S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
     S::Future: Send + 'static,
@@ -508,23 +1077,40 @@ where
         Ok(()).into()
     }
 
-    fn call(&mut self, _: T) -> Self::Future {
+    fn call(&mut self, io: &'a ServerIo) -> Self::Future {
+        let info = io.info().clone();
         let interceptor = self.interceptor.clone();
+        let request_interceptor = self.request_interceptor.clone();
+        let layers = self.layers.clone();
         let svc = self.inner.clone();
         let concurrency_limit = self.concurrency_limit;
-        // let timeout = self.timeout.clone();
+        let compression = self.compression.clone();
+        let timeout = self.timeout;
 
         Box::pin(async move {
             let svc = ServiceBuilder::new()
                 .optional_layer(concurrency_limit.map(ConcurrencyLimitLayer::new))
-                // .optional_layer(timeout.map(TimeoutLayer::new))
+                .optional_layer(timeout.map(GrpcTimeoutLayer::new))
                 .service(svc);
+            let svc = apply_layers(&layers, svc);
+
+            let svc = Svc {
+                inner: svc,
+                compression: compression.clone(),
+                request_interceptor: request_interceptor.clone(),
+                info: info.clone(),
+            };
 
             let svc = if let Some(interceptor) = interceptor {
-                let layered = interceptor.layer(BoxService::new(Svc(svc)));
-                BoxService::new(Svc(layered))
+                let layered = interceptor.layer(BoxService::new(svc));
+                BoxService::new(Svc {
+                    inner: layered,
+                    compression,
+                    request_interceptor,
+                    info,
+                })
             } else {
-                BoxService::new(Svc(svc))
+                BoxService::new(svc)
             };
 
             Ok(svc)
@@ -532,28 +1118,295 @@ where
     }
 }
 
-#[derive(Default, Clone, Debug)]
-#[doc(hidden)]
-pub struct Unimplemented {
-    _p: (),
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::GrpcService;
+    use futures_util::future::poll_fn;
+    use tokio::{net::TcpListener, timer::Timeout};
 
-impl Service<Request<Body>> for Unimplemented {
-    type Response = Response<BoxBody>;
-    type Error = crate::Error;
-    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+    /// A service whose calls never finish.
+    #[derive(Clone)]
+    struct Hung;
 
-    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Ok(()).into()
+    impl ServiceName for Hung {
+        const NAME: &'static str = "test.Hung";
+    }
+
+    impl Service<Request<Body>> for Hung {
+        type Response = Response<BoxBody>;
+        type Error = crate::Error;
+        type Future = future::Pending<Result<Response<BoxBody>, crate::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Ok(()).into()
+        }
+
+        fn call(&mut self, _: Request<Body>) -> Self::Future {
+            future::pending()
+        }
+    }
+
+    /// A service that answers with what it knows about the peer in its
+    /// headers.
+    #[derive(Clone)]
+    struct Peer;
+
+    impl ServiceName for Peer {
+        const NAME: &'static str = "test.Peer";
     }
 
-    fn call(&mut self, _req: Request<Body>) -> Self::Future {
-        future::ok(
-            http::Response::builder()
-                .status(200)
-                .header("grpc-status", "12")
+    impl Service<Request<Body>> for Peer {
+        type Response = Response<BoxBody>;
+        type Error = crate::Error;
+        type Future = future::Ready<Result<Response<BoxBody>, crate::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Ok(()).into()
+        }
+
+        fn call(&mut self, req: Request<Body>) -> Self::Future {
+            let req = crate::Request::from_http(req);
+            let mut res = Response::builder();
+            res.header("grpc-status", "0");
+
+            if let Some(addr) = req.remote_addr() {
+                res.header("remote-addr", addr.to_string());
+            }
+            #[cfg(feature = "tls")]
+            {
+                if let Some(certs) = req.peer_certs() {
+                    res.header("peer-certs", certs.len().to_string());
+                }
+            }
+
+            future::ok(res.body(BoxBody::empty()).unwrap())
+        }
+    }
+
+    /// Call `Peer` through `channel`, returning the response headers.
+    async fn call_peer(channel: &mut Channel) -> http::HeaderMap {
+        poll_fn(|cx| GrpcService::poll_ready(&mut *channel, cx))
+            .await
+            .unwrap();
+
+        let request = Request::builder()
+            .uri("/test.Peer/Call")
+            .body(BoxBody::empty())
+            .unwrap();
+        let response = GrpcService::call(&mut *channel, request).await.unwrap();
+        response.headers().clone()
+    }
+
+    #[tokio::test]
+    async fn serves_in_process() {
+        let mut channel = Server::builder()
+            .add_service(Peer)
+            .serve_in_process()
+            .await
+            .unwrap();
+
+        let headers = call_peer(&mut channel).await;
+        assert_eq!(headers["grpc-status"], "0");
+        assert!(!headers.contains_key("remote-addr"));
+    }
+
+    #[tokio::test]
+    async fn requests_carry_remote_addr() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let serve = Server::builder()
+            .add_service(Peer)
+            .serve_with_connected_incoming(listener.incoming());
+        tokio::spawn(async move {
+            let _ = serve.await;
+        });
+
+        let mut channel = Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let headers = call_peer(&mut channel).await;
+
+        let remote_addr = headers["remote-addr"]
+            .to_str()
+            .unwrap()
+            .parse::<SocketAddr>()
+            .unwrap();
+        assert!(remote_addr.ip().is_loopback());
+        assert_ne!(remote_addr.port(), addr.port());
+    }
+
+    #[tokio::test]
+    async fn lazy_channel_connects_once_server_is_up() {
+        use crate::transport::channel::ConnectivityState;
+
+        // Find a port with nothing listening on it.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let mut channel = Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect_lazy();
+        assert_eq!(channel.state(), ConnectivityState::Idle);
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let serve = Server::builder()
+            .add_service(Peer)
+            .serve_with_incoming(listener.incoming());
+        tokio::spawn(async move {
+            let _ = serve.await;
+        });
+
+        let headers = Timeout::new(call_peer(&mut channel), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(headers["grpc-status"], "0");
+        assert_eq!(channel.state(), ConnectivityState::Ready);
+    }
+
+    #[tokio::test]
+    async fn waiting_request_is_sent_once_server_is_up() {
+        use crate::transport::channel::ConnectivityState;
+        use tokio::timer::delay_for;
+
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let channel = Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .reconnect_backoff(Duration::from_millis(50), Duration::from_millis(100))
+            .wait_for_ready(true)
+            .connect_lazy();
+
+        // Send the first request while nothing listens on the port yet.
+        let (tx, rx) = oneshot::channel();
+        let mut caller = channel.clone();
+        tokio::spawn(async move {
+            let _ = tx.send(call_peer(&mut caller).await);
+        });
+
+        let failed = async {
+            while channel.state() != ConnectivityState::TransientFailure {
+                delay_for(Duration::from_millis(10)).await;
+            }
+        };
+        Timeout::new(failed, Duration::from_secs(5)).await.unwrap();
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let serve = Server::builder()
+            .add_service(Peer)
+            .serve_with_incoming(listener.incoming());
+        tokio::spawn(async move {
+            let _ = serve.await;
+        });
+
+        let headers = Timeout::new(rx, Duration::from_secs(5))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(headers["grpc-status"], "0");
+        assert_eq!(channel.state(), ConnectivityState::Ready);
+    }
+
+    #[cfg(feature = "rustls")]
+    #[tokio::test]
+    async fn requests_carry_peer_certs() {
+        use crate::transport::ClientTlsConfig;
+
+        macro_rules! tls_data {
+            ($file:expr) => {
+                &include_bytes!(concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/../tonic-examples/data/tls/",
+                    $file
+                ))[..]
+            };
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let tls = ServerTlsConfig::with_rustls()
+            .identity(Identity::from_pem(
+                tls_data!("server.pem"),
+                tls_data!("server.key"),
+            ))
+            .client_ca_root(Certificate::from_pem(tls_data!("client_ca.pem")));
+        let serve = Server::builder()
+            .tls_config(tls)
+            .add_service(Peer)
+            .serve_with_connected_incoming(listener.incoming());
+        tokio::spawn(async move {
+            let _ = serve.await;
+        });
+
+        let tls = ClientTlsConfig::with_rustls()
+            .domain_name("localhost")
+            .ca_certificate(Certificate::from_pem(tls_data!("ca.pem")))
+            .identity(Identity::from_pem(
+                tls_data!("client1.pem"),
+                tls_data!("client1.key"),
+            ));
+        let mut channel = Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .tls_config(tls)
+            .connect()
+            .await
+            .unwrap();
+        let headers = call_peer(&mut channel).await;
+
+        let certs = headers["peer-certs"].to_str().unwrap().parse::<usize>();
+        assert!(certs.unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn drain_timeout_closes_hung_connections() {
+        let (connector, incoming) = in_process();
+        let (signal_tx, signal_rx) = oneshot::channel::<()>();
+
+        let serve = Server::builder()
+            .drain_timeout(Duration::from_millis(100))
+            .add_service(Hung)
+            .serve_with_incoming_shutdown(incoming, async {
+                let _ = signal_rx.await;
+            });
+
+        let call = async {
+            let mut channel = Endpoint::from_static("http://localhost")
+                .connect_with_connector(connector)
+                .await
+                .unwrap();
+            poll_fn(|cx| GrpcService::poll_ready(&mut channel, cx))
+                .await
+                .unwrap();
+
+            let request = Request::builder()
+                .uri("/test.Hung/Call")
                 .body(BoxBody::empty())
-                .unwrap(),
-        )
+                .unwrap();
+            let response = GrpcService::call(&mut channel, request);
+
+            tokio::spawn(async move {
+                delay_for(Duration::from_millis(50)).await;
+                let _ = signal_tx.send(());
+            });
+
+            response.await
+        };
+
+        let (served, called) = Timeout::new(future::join(serve, call), Duration::from_secs(5))
+            .await
+            .expect("hung connection was not closed");
+        served.unwrap();
+        assert!(called.is_err());
     }
 }
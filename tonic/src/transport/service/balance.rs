@@ -0,0 +1,509 @@
+use super::connection::{Connection, Request, Response};
+use crate::transport::channel::LoadBalancingPolicy;
+use futures_util::task::{self, ArcWake, AtomicWaker};
+use std::{
+    cmp, fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+use tower::discover::{Change, Discover};
+use tower_service::Service;
+use tracing::debug;
+
+/// Balances requests across discovered connections following a
+/// [`LoadBalancingPolicy`] other than power of two choices, which is handled
+/// by `tower_balance`.
+///
+/// Whenever the connection the policy prefers is not ready, the next one in
+/// the policy's order is used instead. Every entry is polled with its own
+/// waker, so only entries that were woken since they were last found not
+/// ready are polled again.
+pub(crate) struct Balancer<D: Discover> {
+    discover: D,
+    policy: LoadBalancingPolicy,
+    entries: Vec<Entry<D::Key, D::Service>>,
+    /// The round robin cursor.
+    next: usize,
+    /// The entry that was found ready, to be used by the next `call`.
+    ready: Option<usize>,
+    /// The task polling the balancer, woken by the entries.
+    task: Arc<AtomicWaker>,
+}
+
+/// The relative share of requests a balanced service should get.
+pub(crate) trait Weight {
+    fn weight(&self) -> u32;
+}
+
+impl Weight for Connection {
+    fn weight(&self) -> u32 {
+        Connection::weight(self)
+    }
+}
+
+struct Entry<K, S> {
+    key: K,
+    svc: S,
+    weight: i64,
+    /// The running weight of smooth weighted round robin.
+    current: i64,
+    /// Whether `svc` returned ready and has not been called since.
+    ready: bool,
+    notify: Arc<Notify>,
+}
+
+/// Wakes the balancer on behalf of an entry, marking the entry to be polled.
+struct Notify {
+    woken: AtomicBool,
+    task: Arc<AtomicWaker>,
+}
+
+impl ArcWake for Notify {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.woken.store(true, Ordering::SeqCst);
+        arc_self.task.wake();
+    }
+}
+
+impl<D: Discover> Balancer<D> {
+    pub(crate) fn new(discover: D, policy: LoadBalancingPolicy) -> Self {
+        Self {
+            discover,
+            policy,
+            entries: Vec::new(),
+            next: 0,
+            ready: None,
+            task: Arc::new(AtomicWaker::new()),
+        }
+    }
+}
+
+impl<D> Balancer<D>
+where
+    D: Discover + Unpin,
+    D::Key: Eq,
+    D::Service: Weight,
+    D::Error: Into<crate::Error>,
+{
+    fn poll_discover(&mut self, cx: &mut Context<'_>) -> Result<(), crate::Error> {
+        loop {
+            let change = match Pin::new(&mut self.discover).poll_discover(cx) {
+                Poll::Ready(Ok(change)) => change,
+                Poll::Ready(Err(e)) => return Err(e.into()),
+                Poll::Pending => return Ok(()),
+            };
+
+            // Indices move around, so readiness has to be found again.
+            self.ready = None;
+
+            match change {
+                Change::Insert(key, svc) => {
+                    self.entries.retain(|e| e.key != key);
+                    self.entries.push(Entry {
+                        key,
+                        weight: i64::from(svc.weight()),
+                        svc,
+                        current: 0,
+                        ready: false,
+                        notify: Arc::new(Notify {
+                            woken: AtomicBool::new(true),
+                            task: self.task.clone(),
+                        }),
+                    });
+                }
+                Change::Remove(key) => self.entries.retain(|e| e.key != key),
+            }
+        }
+    }
+}
+
+/// The order in which entries are tried, most preferred first.
+fn order<K, S>(policy: LoadBalancingPolicy, next: usize, entries: &[Entry<K, S>]) -> Vec<usize> {
+    let n = entries.len();
+
+    match policy {
+        LoadBalancingPolicy::PickFirst | LoadBalancingPolicy::PowerOfTwoChoices => (0..n).collect(),
+        LoadBalancingPolicy::RoundRobin => (0..n).map(|i| (next + i) % n).collect(),
+        LoadBalancingPolicy::WeightedRoundRobin => {
+            let mut order = (0..n).collect::<Vec<_>>();
+            // Entries with weight 0 are only a fallback. A stable sort keeps
+            // ties in insertion order.
+            order.sort_by_key(|&i| {
+                let entry = &entries[i];
+                (
+                    entry.weight == 0,
+                    cmp::Reverse(entry.current + entry.weight),
+                )
+            });
+            order
+        }
+    }
+}
+
+/// Advance the policy's state after entry `i` has been picked.
+fn select<K, S>(
+    policy: LoadBalancingPolicy,
+    next: &mut usize,
+    entries: &mut [Entry<K, S>],
+    i: usize,
+) {
+    match policy {
+        LoadBalancingPolicy::RoundRobin => *next = i + 1,
+        LoadBalancingPolicy::WeightedRoundRobin => {
+            let total = entries.iter().map(|e| e.weight).sum::<i64>();
+            for entry in entries.iter_mut() {
+                entry.current += entry.weight;
+            }
+            entries[i].current -= total;
+        }
+        LoadBalancingPolicy::PickFirst | LoadBalancingPolicy::PowerOfTwoChoices => {}
+    }
+}
+
+impl<D> Service<Request> for Balancer<D>
+where
+    D: Discover + Unpin,
+    D::Key: Eq,
+    D::Service: Service<Request, Response = Response, Error = crate::Error> + Weight,
+    D::Error: Into<crate::Error>,
+{
+    type Response = Response;
+    type Error = crate::Error;
+    type Future = <D::Service as Service<Request>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.task.register(cx.waker());
+        self.poll_discover(cx)?;
+
+        if self.ready.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+
+        for i in order(self.policy, self.next, &self.entries) {
+            let entry = &mut self.entries[i];
+
+            if !entry.ready && entry.notify.woken.swap(false, Ordering::SeqCst) {
+                let waker = task::waker(entry.notify.clone());
+
+                match entry.svc.poll_ready(&mut Context::from_waker(&waker)) {
+                    Poll::Ready(Ok(())) => entry.ready = true,
+                    Poll::Ready(Err(error)) => {
+                        // A failed connection re-dials the next time it is
+                        // polled. Nothing else is going to wake the balancer
+                        // for it, so it is woken right away.
+                        debug!("balanced connection failed: {}", error);
+                        waker.wake_by_ref();
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
+            if entry.ready {
+                select(self.policy, &mut self.next, &mut self.entries, i);
+                self.ready = Some(i);
+                return Poll::Ready(Ok(()));
+            }
+        }
+
+        Poll::Pending
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let i = self
+            .ready
+            .take()
+            .expect("poll_ready must be called before call");
+
+        let entry = &mut self.entries[i];
+        // Readiness is used up by the call, so the entry is polled again.
+        entry.ready = false;
+        entry.notify.woken.store(true, Ordering::SeqCst);
+        entry.svc.call(req)
+    }
+}
+
+impl<D: Discover> fmt::Debug for Balancer<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Balancer")
+            .field("policy", &self.policy)
+            .field("endpoints", &self.entries.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::BoxBody, transport::channel::ResponseBody};
+    use futures_util::future::{self, Ready};
+    use std::{collections::VecDeque, sync::Mutex, task::Waker};
+
+    fn entries(weights: &[i64]) -> Vec<Entry<usize, ()>> {
+        let task = Arc::new(AtomicWaker::new());
+
+        weights
+            .iter()
+            .enumerate()
+            .map(|(key, &weight)| Entry {
+                key,
+                svc: (),
+                weight,
+                current: 0,
+                ready: false,
+                notify: Arc::new(Notify {
+                    woken: AtomicBool::new(true),
+                    task: task.clone(),
+                }),
+            })
+            .collect()
+    }
+
+    fn picks(policy: LoadBalancingPolicy, weights: &[i64], rounds: usize) -> Vec<usize> {
+        let mut entries = entries(weights);
+        let mut next = 0;
+
+        (0..rounds)
+            .map(|_| {
+                let i = order(policy, next, &entries)[0];
+                select(policy, &mut next, &mut entries, i);
+                i
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pick_first_sticks_to_the_first_endpoint() {
+        let mut entries = entries(&[1, 1, 1]);
+        assert_eq!(
+            order(LoadBalancingPolicy::PickFirst, 0, &entries),
+            vec![0, 1, 2]
+        );
+
+        select(LoadBalancingPolicy::PickFirst, &mut 0, &mut entries, 0);
+        assert_eq!(
+            order(LoadBalancingPolicy::PickFirst, 0, &entries),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn round_robin_rotates() {
+        assert_eq!(
+            picks(LoadBalancingPolicy::RoundRobin, &[1, 1, 1], 5),
+            vec![0, 1, 2, 0, 1]
+        );
+        assert_eq!(
+            order(LoadBalancingPolicy::RoundRobin, 2, &entries(&[1, 1, 1])),
+            vec![2, 0, 1]
+        );
+    }
+
+    #[test]
+    fn weighted_round_robin_follows_weights() {
+        let picks = picks(LoadBalancingPolicy::WeightedRoundRobin, &[5, 1, 1], 7);
+        assert_eq!(picks, vec![0, 0, 1, 0, 2, 0, 0]);
+    }
+
+    #[derive(Default)]
+    struct MockState {
+        ready: bool,
+        /// Whether the next poll fails.
+        fail: bool,
+        polls: usize,
+        waker: Option<Waker>,
+    }
+
+    struct Mock {
+        id: usize,
+        weight: u32,
+        state: Arc<Mutex<MockState>>,
+        calls: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl Mock {
+        fn set_ready(&self, ready: bool) {
+            let mut state = self.state.lock().unwrap();
+            state.ready = ready;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+
+        fn fail_once(&self) {
+            self.state.lock().unwrap().fail = true;
+        }
+
+        fn polls(&self) -> usize {
+            self.state.lock().unwrap().polls
+        }
+    }
+
+    impl Weight for Mock {
+        fn weight(&self) -> u32 {
+            self.weight
+        }
+    }
+
+    impl Service<Request> for Mock {
+        type Response = Response;
+        type Error = crate::Error;
+        type Future = Ready<Result<Response, crate::Error>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            let mut state = self.state.lock().unwrap();
+            state.polls += 1;
+
+            if state.fail {
+                state.fail = false;
+                Poll::Ready(Err("connection refused".into()))
+            } else if state.ready {
+                Poll::Ready(Ok(()))
+            } else {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+
+        fn call(&mut self, _: Request) -> Self::Future {
+            self.calls.lock().unwrap().push(self.id);
+            future::ok(Response::new(ResponseBody::empty()))
+        }
+    }
+
+    struct Changes(VecDeque<Change<usize, Mock>>);
+
+    impl Discover for Changes {
+        type Key = usize;
+        type Service = Mock;
+        type Error = crate::Error;
+
+        fn poll_discover(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Change<usize, Mock>, crate::Error>> {
+            match self.0.pop_front() {
+                Some(change) => Poll::Ready(Ok(change)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    /// A balancer over ready mocks with the given weights, and handles to
+    /// the mocks' state and the ids of the mocks that were called.
+    fn balancer(
+        policy: LoadBalancingPolicy,
+        weights: &[u32],
+    ) -> (Balancer<Changes>, Vec<Mock>, Arc<Mutex<Vec<usize>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut changes = VecDeque::new();
+        let mut handles = Vec::new();
+
+        for (id, &weight) in weights.iter().enumerate() {
+            let state = Arc::new(Mutex::new(MockState {
+                ready: true,
+                ..MockState::default()
+            }));
+            let mock = |state| Mock {
+                id,
+                weight,
+                state,
+                calls: calls.clone(),
+            };
+
+            changes.push_back(Change::Insert(id, mock(state.clone())));
+            handles.push(mock(state));
+        }
+
+        (Balancer::new(Changes(changes), policy), handles, calls)
+    }
+
+    /// Send a request through the balancer, returning the id of the mock
+    /// that got it.
+    fn pick(balancer: &mut Balancer<Changes>, calls: &Mutex<Vec<usize>>) -> Option<usize> {
+        let mut cx = Context::from_waker(task::noop_waker_ref());
+
+        match balancer.poll_ready(&mut cx) {
+            Poll::Ready(result) => result.unwrap(),
+            Poll::Pending => return None,
+        }
+
+        let _ = balancer.call(http::Request::new(BoxBody::empty()));
+        calls.lock().unwrap().pop()
+    }
+
+    #[test]
+    fn balancer_follows_weights() {
+        let (mut balancer, _mocks, calls) =
+            balancer(LoadBalancingPolicy::WeightedRoundRobin, &[5, 1, 1]);
+
+        let picks = (0..7)
+            .map(|_| pick(&mut balancer, &calls).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(picks, vec![0, 0, 1, 0, 2, 0, 0]);
+    }
+
+    #[test]
+    fn balancer_uses_weight_zero_only_as_fallback() {
+        let (mut balancer, mocks, calls) =
+            balancer(LoadBalancingPolicy::WeightedRoundRobin, &[0, 1, 1, 1]);
+
+        for _ in 0..6 {
+            assert_ne!(pick(&mut balancer, &calls), Some(0));
+        }
+
+        mocks[1].set_ready(false);
+        mocks[2].set_ready(false);
+        mocks[3].set_ready(false);
+        assert_eq!(pick(&mut balancer, &calls), Some(0));
+
+        mocks[0].set_ready(false);
+        assert_eq!(pick(&mut balancer, &calls), None);
+    }
+
+    #[test]
+    fn balancer_polls_pending_entries_only_when_woken() {
+        let (mut balancer, mocks, calls) = balancer(LoadBalancingPolicy::PickFirst, &[1, 1]);
+        mocks[0].set_ready(false);
+
+        assert_eq!(pick(&mut balancer, &calls), Some(1));
+        assert_eq!(pick(&mut balancer, &calls), Some(1));
+        assert_eq!(mocks[0].polls(), 1);
+
+        mocks[0].set_ready(true);
+        assert_eq!(pick(&mut balancer, &calls), Some(0));
+        assert_eq!(mocks[0].polls(), 2);
+    }
+
+    #[derive(Default)]
+    struct Woken(AtomicBool);
+
+    impl ArcWake for Woken {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn balancer_is_woken_when_every_entry_fails() {
+        let (mut balancer, mocks, _calls) = balancer(LoadBalancingPolicy::RoundRobin, &[1, 1]);
+        for mock in &mocks {
+            mock.fail_once();
+        }
+
+        let woken = Arc::new(Woken::default());
+        let waker = task::waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        assert!(balancer.poll_ready(&mut cx).is_pending());
+        assert!(woken.0.load(Ordering::SeqCst));
+
+        match balancer.poll_ready(&mut cx) {
+            Poll::Ready(result) => result.unwrap(),
+            Poll::Pending => panic!("balancer should be ready once entries recover"),
+        }
+    }
+}
@@ -1,6 +1,14 @@
-use super::{connector, layer::ServiceBuilderExt, reconnect::Reconnect, AddOrigin};
+use super::{
+    connectivity::Reporter,
+    connector::{ConnectTimeout, Connector},
+    layer::{apply_layers, ServiceBuilderExt},
+    load::{Cost, LoadTracker, ResponseBody},
+    reconnect::Reconnect,
+    AddOrigin, KeepAlive, UDS_SCHEME,
+};
 use crate::{body::BoxBody, transport::Endpoint};
-use hyper::client::conn::Builder;
+use http::Uri;
+use hyper::client::conn::{Builder, SendRequest};
 use hyper::client::service::Connect as HyperConnect;
 use std::{
     fmt,
@@ -16,45 +24,140 @@ use tower::{
     ServiceBuilder,
 };
 use tower_load::Load;
+use tower_make::MakeConnection;
 use tower_service::Service;
 
 pub(crate) type Request = http::Request<BoxBody>;
-pub(crate) type Response = http::Response<hyper::Body>;
+pub(crate) type Response = http::Response<ResponseBody>;
 
 pub(crate) struct Connection {
-    inner: BoxService<Request, Response, crate::Error>,
+    inner: BoxService<Request, http::Response<hyper::Body>, crate::Error>,
+    load: LoadTracker,
+    weight: u32,
 }
 
 impl Connection {
-    pub(crate) async fn new(endpoint: Endpoint) -> Result<Self, crate::Error> {
-        #[cfg(feature = "tls")]
-        let connector = connector(endpoint.tls.clone());
+    pub(crate) async fn new<C>(
+        connector: C,
+        endpoint: Endpoint,
+        reporter: Reporter,
+    ) -> Result<Self, crate::Error>
+    where
+        C: MakeConnection<Uri> + Send + 'static,
+        C::Connection: Unpin + Send + 'static,
+        C::Future: Send + 'static,
+        C::Error: Into<crate::Error> + Send,
+    {
+        let mut connector = connect(connector, &endpoint);
+        let initial_conn = connector.call(endpoint.uri.clone()).await?;
 
-        #[cfg(not(feature = "tls"))]
-        let connector = connector();
+        Ok(Self::build(
+            connector,
+            endpoint,
+            reporter,
+            Some(initial_conn),
+        ))
+    }
 
-        let settings = Builder::new()
-            .http2_initial_stream_window_size(endpoint.init_stream_window_size)
-            .http2_initial_connection_window_size(endpoint.init_connection_window_size)
-            .http2_only(true)
-            .clone();
+    /// Create a connection that is only established once it is first
+    /// polled for readiness.
+    pub(crate) fn lazy<C>(connector: C, endpoint: Endpoint, reporter: Reporter) -> Self
+    where
+        C: MakeConnection<Uri> + Send + 'static,
+        C::Connection: Unpin + Send + 'static,
+        C::Future: Send + 'static,
+        C::Error: Into<crate::Error> + Send,
+    {
+        let connector = connect(connector, &endpoint);
+
+        Self::build(connector, endpoint, reporter, None)
+    }
+
+    fn build<C>(
+        connector: Connect<C>,
+        endpoint: Endpoint,
+        reporter: Reporter,
+        initial_conn: Option<SendRequest<BoxBody>>,
+    ) -> Self
+    where
+        C: MakeConnection<Uri> + Send + 'static,
+        C::Connection: Unpin + Send + 'static,
+        C::Future: Send + 'static,
+        C::Error: Into<crate::Error> + Send,
+    {
+        let origin = origin(&endpoint.uri);
 
         let stack = ServiceBuilder::new()
-            .layer_fn(|s| AddOrigin::new(s, endpoint.uri.clone()))
+            .layer_fn(|s| AddOrigin::new(s, origin.clone()))
             .optional_layer(endpoint.timeout.map(TimeoutLayer::new))
             .optional_layer(endpoint.concurrency_limit.map(ConcurrencyLimitLayer::new))
             .optional_layer(endpoint.rate_limit.map(|(l, d)| RateLimitLayer::new(l, d)))
             .into_inner();
 
-        let mut connector = HyperConnect::new(connector, settings);
-        let initial_conn = connector.call(endpoint.uri.clone()).await?;
-        let conn = Reconnect::new(initial_conn, connector, endpoint.uri.clone());
+        let conn = Reconnect::new(
+            initial_conn,
+            connector,
+            endpoint.uri.clone(),
+            endpoint.reconnect_backoff,
+            reporter,
+        );
+
+        let inner = apply_layers(&endpoint.layers, stack.layer(conn));
+
+        Self {
+            inner,
+            load: LoadTracker::new(endpoint.load),
+            weight: endpoint.weight,
+        }
+    }
 
-        let inner = stack.layer(conn);
+    /// The relative share of requests this connection should get from a
+    /// weighted balancer.
+    pub(crate) fn weight(&self) -> u32 {
+        self.weight
+    }
+}
 
-        Ok(Self {
-            inner: BoxService::new(inner),
-        })
+type Connect<C> = ConnectTimeout<HyperConnect<Connector<C>, BoxBody, Uri>>;
+
+/// Dial HTTP/2 connections with `connector`, configured from `endpoint`.
+///
+/// The connect timeout covers everything up to a usable connection,
+/// including the TLS and HTTP/2 handshakes.
+fn connect<C>(connector: C, endpoint: &Endpoint) -> Connect<C> {
+    let keep_alive = KeepAlive {
+        interval: endpoint.keep_alive_interval,
+        timeout: endpoint.keep_alive_timeout,
+        while_idle: endpoint.keep_alive_while_idle,
+    };
+
+    #[cfg(feature = "tls")]
+    let connector = super::connector(connector, keep_alive, endpoint.tls.clone());
+
+    #[cfg(not(feature = "tls"))]
+    let connector = super::connector(connector, keep_alive);
+
+    let settings = Builder::new()
+        .http2_initial_stream_window_size(endpoint.init_stream_window_size)
+        .http2_initial_connection_window_size(endpoint.init_connection_window_size)
+        .http2_only(true)
+        .clone();
+
+    ConnectTimeout::new(
+        HyperConnect::new(connector, settings),
+        endpoint.connect_timeout,
+    )
+}
+
+/// The origin requests on a connection to `uri` are sent with.
+///
+/// A Unix domain socket has no meaningful host, so requests over one are
+/// addressed to `localhost` instead.
+fn origin(uri: &Uri) -> Uri {
+    if uri.scheme_str() == Some(UDS_SCHEME) {
+        Uri::from_static("http://localhost")
+    } else {
+        uri.clone()
     }
 }
 
@@ -70,15 +173,23 @@ impl Service<Request> for Connection {
     }
 
     fn call(&mut self, req: Request) -> Self::Future {
-        self.inner.call(req)
+        let mut handle = self.load.start();
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            handle.on_response();
+            // The request is in flight until its response stream ends.
+            Ok(res.map(|body| ResponseBody::new(body, handle)))
+        })
     }
 }
 
 impl Load for Connection {
-    type Metric = usize;
+    type Metric = Cost;
 
     fn load(&self) -> Self::Metric {
-        0
+        self.load.cost()
     }
 }
 
@@ -0,0 +1,152 @@
+use crate::transport::channel::ConnectivityState;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::watch;
+
+/// Combines the states of all the connections of a channel into the state
+/// of the channel.
+#[derive(Clone)]
+pub(crate) struct Connectivity {
+    shared: Arc<Mutex<Shared>>,
+    rx: watch::Receiver<ConnectivityState>,
+}
+
+struct Shared {
+    tx: watch::Sender<ConnectivityState>,
+    current: ConnectivityState,
+    states: HashMap<u64, ConnectivityState>,
+    next_id: u64,
+}
+
+/// Reports the state of one connection until dropped.
+pub(crate) struct Reporter {
+    id: u64,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Connectivity {
+    pub(crate) fn new() -> Self {
+        let (tx, rx) = watch::channel(ConnectivityState::Idle);
+
+        let shared = Shared {
+            tx,
+            current: ConnectivityState::Idle,
+            states: HashMap::new(),
+            next_id: 0,
+        };
+
+        Connectivity {
+            shared: Arc::new(Mutex::new(shared)),
+            rx,
+        }
+    }
+
+    /// Register a connection that is about to connect.
+    pub(crate) fn reporter(&self) -> Reporter {
+        let mut shared = self.shared.lock().unwrap();
+        let id = shared.next_id;
+        shared.next_id += 1;
+        shared.states.insert(id, ConnectivityState::Connecting);
+        shared.update();
+
+        Reporter {
+            id,
+            shared: self.shared.clone(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> ConnectivityState {
+        *self.rx.get_ref()
+    }
+
+    pub(crate) fn watch(&self) -> watch::Receiver<ConnectivityState> {
+        self.rx.clone()
+    }
+}
+
+impl Shared {
+    fn update(&mut self) {
+        let state = aggregate(self.states.values().copied());
+
+        if state != self.current {
+            self.current = state;
+            // `Connectivity` holds a receiver, so this cannot fail.
+            let _ = self.tx.broadcast(state);
+        }
+    }
+}
+
+impl Reporter {
+    pub(crate) fn set(&self, state: ConnectivityState) {
+        let mut shared = self.shared.lock().unwrap();
+
+        if shared.states.insert(self.id, state) != Some(state) {
+            shared.update();
+        }
+    }
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.states.remove(&self.id);
+            shared.update();
+        }
+    }
+}
+
+/// The state of a channel is the best state of any of its connections.
+fn aggregate(states: impl Iterator<Item = ConnectivityState>) -> ConnectivityState {
+    let rank = |state| match state {
+        ConnectivityState::Ready => 3,
+        ConnectivityState::Connecting => 2,
+        ConnectivityState::Idle => 1,
+        ConnectivityState::TransientFailure => 0,
+    };
+
+    states.max_by_key(|&state| rank(state)).unwrap_or_default()
+}
+
+impl fmt::Debug for Connectivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connectivity")
+            .field("state", &self.get())
+            .finish()
+    }
+}
+
+impl fmt::Debug for Reporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reporter").field("id", &self.id).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_state_is_the_best_connection_state() {
+        let connectivity = Connectivity::new();
+        assert_eq!(connectivity.get(), ConnectivityState::Idle);
+
+        let a = connectivity.reporter();
+        let b = connectivity.reporter();
+        assert_eq!(connectivity.get(), ConnectivityState::Connecting);
+
+        a.set(ConnectivityState::TransientFailure);
+        assert_eq!(connectivity.get(), ConnectivityState::Connecting);
+
+        b.set(ConnectivityState::Ready);
+        assert_eq!(connectivity.get(), ConnectivityState::Ready);
+
+        drop(b);
+        assert_eq!(connectivity.get(), ConnectivityState::TransientFailure);
+
+        drop(a);
+        assert_eq!(connectivity.get(), ConnectivityState::Idle);
+    }
+}
@@ -1,72 +1,198 @@
 use super::io::BoxedIo;
+use super::keepalive::KeepAlive;
 #[cfg(feature = "tls")]
 use super::tls::TlsConnector;
+use crate::transport::Endpoint;
 use http::Uri;
 use hyper::client::connect::HttpConnector;
 use std::future::Future;
+use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::timer::Timeout;
 use tower_make::MakeConnection;
 use tower_service::Service;
 
+/// The scheme of URIs that point at a Unix domain socket.
+pub(crate) const UDS_SCHEME: &str = "unix";
+
+type ConnectFuture = Pin<Box<dyn Future<Output = Result<BoxedIo, crate::Error>> + Send + 'static>>;
+
 #[cfg(not(feature = "tls"))]
-pub(crate) fn connector() -> HttpConnector {
-    let mut http = HttpConnector::new();
-    http.enforce_http(false);
-    http.set_nodelay(true);
-    http
+pub(crate) fn connector<C>(inner: C, keep_alive: KeepAlive) -> Connector<C> {
+    Connector::new(inner, keep_alive)
 }
 
 #[cfg(feature = "tls")]
-pub(crate) fn connector(tls: Option<TlsConnector>) -> Connector {
-    Connector::new(tls)
+pub(crate) fn connector<C>(
+    inner: C,
+    keep_alive: KeepAlive,
+    tls: Option<TlsConnector>,
+) -> Connector<C> {
+    Connector::new(inner, keep_alive, tls)
 }
 
-pub(crate) struct Connector {
-    http: HttpConnector,
+/// Layers TLS and keepalive pings, when configured, on top of the
+/// connections made by `C`.
+pub(crate) struct Connector<C> {
+    inner: C,
+    keep_alive: KeepAlive,
     #[cfg(feature = "tls")]
     tls: Option<TlsConnector>,
 }
 
-impl Connector {
-    #[cfg(feature = "tls")]
-    pub(crate) fn new(tls: Option<TlsConnector>) -> Self {
-        let mut http = HttpConnector::new();
-        http.enforce_http(false);
-        http.set_nodelay(true);
+impl<C> Connector<C> {
+    #[cfg(not(feature = "tls"))]
+    pub(crate) fn new(inner: C, keep_alive: KeepAlive) -> Self {
+        Self { inner, keep_alive }
+    }
 
-        Self { http, tls }
+    #[cfg(feature = "tls")]
+    pub(crate) fn new(inner: C, keep_alive: KeepAlive, tls: Option<TlsConnector>) -> Self {
+        Self {
+            inner,
+            keep_alive,
+            tls,
+        }
     }
 }
 
-impl Service<Uri> for Connector {
+impl<C> Service<Uri> for Connector<C>
+where
+    C: MakeConnection<Uri>,
+    C::Connection: Unpin + Send + 'static,
+    C::Future: Send + 'static,
+    C::Error: Into<crate::Error> + Send,
+{
     type Response = BoxedIo;
     type Error = crate::Error;
-
-    type Future =
-        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+    type Future = ConnectFuture;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        MakeConnection::poll_ready(&mut self.http, cx).map_err(Into::into)
+        MakeConnection::poll_ready(&mut self.inner, cx).map_err(Into::into)
     }
 
     fn call(&mut self, uri: Uri) -> Self::Future {
-        let connect = MakeConnection::make_connection(&mut self.http, uri);
+        let connect = MakeConnection::make_connection(&mut self.inner, uri);
+        let keep_alive = self.keep_alive;
 
         #[cfg(feature = "tls")]
         let tls = self.tls.clone();
 
         Box::pin(async move {
-            let io = connect.await?;
+            let io = BoxedIo::new(connect.await.map_err(Into::into)?);
 
             #[cfg(feature = "tls")]
             {
                 if let Some(tls) = tls {
-                    let conn = tls.connect(io).await?;
-                    return Ok(BoxedIo::new(conn));
+                    let io = tls.connect(io).await?;
+                    return Ok(keep_alive.client(io));
                 }
             }
 
+            Ok(keep_alive.client(io))
+        })
+    }
+}
+
+/// Fails establishing a connection with `M` if it does not finish within
+/// `timeout`.
+///
+/// This wraps the whole connection setup, so the TLS and HTTP/2 handshakes
+/// count towards the timeout along with dialing.
+pub(crate) struct ConnectTimeout<M> {
+    inner: M,
+    timeout: Option<Duration>,
+}
+
+impl<M> ConnectTimeout<M> {
+    pub(crate) fn new(inner: M, timeout: Option<Duration>) -> Self {
+        ConnectTimeout { inner, timeout }
+    }
+}
+
+impl<M, Target> Service<Target> for ConnectTimeout<M>
+where
+    M: Service<Target>,
+    M::Future: Send + 'static,
+    M::Error: Into<crate::Error>,
+{
+    type Response = M::Response;
+    type Error = crate::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<M::Response, crate::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, target: Target) -> Self::Future {
+        Box::pin(with_timeout(self.inner.call(target), self.timeout))
+    }
+}
+
+/// The connector used unless one is supplied, dialing a Unix domain socket
+/// for `unix` URIs and TCP for everything else.
+///
+/// The TCP options are taken from the endpoint.
+pub(crate) struct DefaultConnector {
+    http: HttpConnector,
+}
+
+impl DefaultConnector {
+    pub(crate) fn new(endpoint: &Endpoint) -> Self {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_nodelay(endpoint.tcp_nodelay);
+        http.set_keepalive(endpoint.tcp_keepalive);
+        http.set_local_address(endpoint.local_address);
+
+        Self { http }
+    }
+}
+
+/// Fail `connect` if it does not finish within `timeout`.
+async fn with_timeout<F, T, E>(connect: F, timeout: Option<Duration>) -> Result<T, crate::Error>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<crate::Error>,
+{
+    let res = match timeout {
+        Some(timeout) => Timeout::new(connect, timeout)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))?,
+        None => connect.await,
+    };
+
+    res.map_err(Into::into)
+}
+
+impl Service<Uri> for DefaultConnector {
+    type Response = BoxedIo;
+    type Error = crate::Error;
+    type Future = ConnectFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        MakeConnection::poll_ready(&mut self.http, cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        #[cfg(unix)]
+        {
+            if uri.scheme_str() == Some(UDS_SCHEME) {
+                let path = uri.path().to_owned();
+                return Box::pin(async move {
+                    let io = UnixStream::connect(path).await?;
+                    Ok(BoxedIo::new(io))
+                });
+            }
+        }
+
+        let connect = MakeConnection::make_connection(&mut self.http, uri);
+        Box::pin(async move {
+            let io = connect.await?;
             Ok(BoxedIo::new(io))
         })
     }
@@ -1,27 +1,51 @@
-use super::connection::Connection;
-use crate::transport::Endpoint;
+use super::{connection::Connection, connector::DefaultConnector, Connectivity};
+use crate::transport::{
+    dns::{Resolve, ResolveFuture},
+    Endpoint,
+};
+use futures_core::Stream;
+use futures_util::task::AtomicWaker;
+use http::Uri;
 use std::{
-    collections::VecDeque,
+    cmp,
+    collections::{HashSet, VecDeque},
     fmt,
     future::Future,
+    marker::PhantomData,
+    net::SocketAddr,
     pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
+use tokio::timer::{delay, Delay};
 use tower::discover::{Change, Discover};
+use tower_service::Service;
+use tracing::debug;
+
+/// How soon to re-resolve after a connection to a resolved address was lost
+/// or could not be established.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
+/// Inserts a lazily connected connection for each endpoint of a fixed list.
+///
+/// Like with [`DynamicServiceStream`], an endpoint that fails to connect
+/// backs off and reconnects on its own, while the others are used.
 pub(crate) struct ServiceList {
     list: VecDeque<Endpoint>,
-    connecting:
-        Option<Pin<Box<dyn Future<Output = Result<Connection, crate::Error>> + Send + 'static>>>,
     i: usize,
+    connectivity: Connectivity,
 }
 
 impl ServiceList {
-    pub(crate) fn new(list: Vec<Endpoint>) -> Self {
+    pub(crate) fn new(list: Vec<Endpoint>, connectivity: Connectivity) -> Self {
         Self {
             list: list.into(),
-            connecting: None,
             i: 0,
+            connectivity,
         }
     }
 }
@@ -33,35 +57,415 @@ impl Discover for ServiceList {
 
     fn poll_discover(
         mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
+        _cx: &mut Context<'_>,
     ) -> Poll<Result<Change<Self::Key, Self::Service>, Self::Error>> {
-        loop {
-            if let Some(connecting) = &mut self.connecting {
-                let svc = futures_core::ready!(Pin::new(connecting).poll(cx))?;
-                self.connecting = None;
+        match self.list.pop_front() {
+            Some(endpoint) => {
+                let reporter = self.connectivity.reporter();
+                let svc = Connection::lazy(DefaultConnector::new(&endpoint), endpoint, reporter);
 
                 let i = self.i;
                 self.i += 1;
 
-                let change = Ok(Change::Insert(i, svc));
+                Poll::Ready(Ok(Change::Insert(i, svc)))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl fmt::Debug for ServiceList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceList")
+            .field("list", &self.list)
+            .finish()
+    }
+}
+
+/// Follows a stream of endpoint changes, inserting a lazily connected
+/// connection for every inserted endpoint.
+///
+/// Connecting, and reconnecting with backoff after a failure, happens when
+/// the balancer polls a connection, so an endpoint that is slow or fails to
+/// connect neither holds up the changes after it nor gets dropped.
+pub(crate) struct DynamicServiceStream<K, S> {
+    changes: S,
+    connectivity: Connectivity,
+    _key: PhantomData<fn(K)>,
+}
+
+impl<K, S> DynamicServiceStream<K, S> {
+    pub(crate) fn new(changes: S, connectivity: Connectivity) -> Self {
+        Self {
+            changes,
+            connectivity,
+            _key: PhantomData,
+        }
+    }
+}
 
-                return Poll::Ready(change);
+impl<K, S> Discover for DynamicServiceStream<K, S>
+where
+    S: Stream<Item = Change<K, Endpoint>> + Unpin,
+{
+    type Key = K;
+    type Service = Connection;
+    type Error = crate::Error;
+
+    fn poll_discover(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Change<Self::Key, Self::Service>, Self::Error>> {
+        match futures_core::ready!(Pin::new(&mut self.changes).poll_next(cx)) {
+            Some(Change::Insert(key, endpoint)) => {
+                let reporter = self.connectivity.reporter();
+                let svc = Connection::lazy(DefaultConnector::new(&endpoint), endpoint, reporter);
+                Poll::Ready(Ok(Change::Insert(key, svc)))
             }
+            Some(Change::Remove(key)) => Poll::Ready(Ok(Change::Remove(key))),
+            // The balancer keeps using the endpoints it already has.
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<K, S> fmt::Debug for DynamicServiceStream<K, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynamicServiceStream").finish()
+    }
+}
 
-            if let Some(endpoint) = self.list.pop_front() {
-                let fut = Connection::new(endpoint);
-                self.connecting = Some(Box::pin(fut));
+/// Resolves the host of an endpoint and keeps one lazily connected
+/// connection to each of its addresses.
+///
+/// The host is re-resolved every `interval`, and sooner when a connection to
+/// one of its addresses is lost or cannot be established. A resolution that
+/// fails or yields no addresses leaves the current connections in place.
+pub(crate) struct DnsDiscover<R> {
+    endpoint: Endpoint,
+    host: String,
+    port: u16,
+    resolver: R,
+    interval: Duration,
+    timer: Delay,
+    resolving: Option<ResolveFuture>,
+    addrs: HashSet<SocketAddr>,
+    changes: VecDeque<Change<SocketAddr, ()>>,
+    reresolve: Arc<Reresolve>,
+    connectivity: Connectivity,
+}
+
+/// Asks a `DnsDiscover` to resolve again soon, on behalf of its connections.
+#[derive(Default)]
+struct Reresolve {
+    requested: AtomicBool,
+    task: AtomicWaker,
+}
+
+impl Reresolve {
+    fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.task.wake();
+    }
+}
+
+impl<R> DnsDiscover<R> {
+    pub(crate) fn new(
+        endpoint: Endpoint,
+        resolver: R,
+        interval: Duration,
+        connectivity: Connectivity,
+    ) -> Self {
+        let host = endpoint.uri.host().unwrap_or_default().to_string();
+        let port = endpoint.uri.port_u16().unwrap_or_else(|| {
+            if endpoint.uri.scheme_str() == Some("https") {
+                443
             } else {
-                return Poll::Pending;
+                80
             }
+        });
+
+        Self {
+            endpoint,
+            host,
+            port,
+            resolver,
+            interval,
+            timer: delay(Instant::now()),
+            resolving: None,
+            addrs: HashSet::new(),
+            changes: VecDeque::new(),
+            reresolve: Arc::new(Reresolve::default()),
+            connectivity,
         }
     }
+
+    fn update(&mut self, ips: Vec<std::net::IpAddr>) {
+        let port = self.port;
+        let resolved = ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect::<HashSet<_>>();
+
+        for addr in self.addrs.difference(&resolved) {
+            self.changes.push_back(Change::Remove(*addr));
+        }
+        for addr in resolved.difference(&self.addrs) {
+            self.changes.push_back(Change::Insert(*addr, ()));
+        }
+
+        self.addrs = resolved;
+    }
+
+    fn connect(&self, addr: SocketAddr) -> Connection {
+        let connector = AddrConnector {
+            addr,
+            inner: DefaultConnector::new(&self.endpoint),
+            reresolve: self.reresolve.clone(),
+            dialed: false,
+        };
+        let reporter = self.connectivity.reporter();
+        Connection::lazy(connector, self.endpoint.clone(), reporter)
+    }
 }
 
-impl fmt::Debug for ServiceList {
+impl<R: Resolve + Unpin> Discover for DnsDiscover<R> {
+    type Key = SocketAddr;
+    type Service = Connection;
+    type Error = crate::Error;
+
+    fn poll_discover(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Change<Self::Key, Self::Service>, Self::Error>> {
+        loop {
+            if let Some(change) = self.changes.pop_front() {
+                let change = match change {
+                    Change::Insert(addr, ()) => Change::Insert(addr, self.connect(addr)),
+                    Change::Remove(addr) => Change::Remove(addr),
+                };
+                return Poll::Ready(Ok(change));
+            }
+
+            if let Some(resolving) = &mut self.resolving {
+                let res = futures_core::ready!(Pin::new(resolving).poll(cx));
+                self.resolving = None;
+
+                match res {
+                    Ok(ref ips) if ips.is_empty() => {
+                        debug!("resolving {} returned no addresses", self.host)
+                    }
+                    Ok(ips) => self.update(ips),
+                    Err(error) => debug!("failed to resolve {}: {}", self.host, error),
+                }
+
+                let next = Instant::now() + self.interval;
+                self.timer.reset(next);
+                continue;
+            }
+
+            self.reresolve.task.register(cx.waker());
+            if self.reresolve.requested.swap(false, Ordering::SeqCst) {
+                let retry = Instant::now() + cmp::min(self.interval, RETRY_INTERVAL);
+                if retry < self.timer.deadline() {
+                    self.timer.reset(retry);
+                }
+            }
+
+            futures_core::ready!(Pin::new(&mut self.timer).poll(cx));
+            let me = &mut *self;
+            me.resolving = Some(me.resolver.resolve(&me.host));
+        }
+    }
+}
+
+impl<R> fmt::Debug for DnsDiscover<R> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("ServiceList")
-            .field("list", &self.list)
+        f.debug_struct("DnsDiscover")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("addrs", &self.addrs)
             .finish()
     }
 }
+
+/// Dials a fixed address instead of the host of the URI it is given, so
+/// that the endpoint's host is still used as the origin and for TLS.
+///
+/// Dialing again, which happens once the previous connection was lost, and
+/// failing to dial both ask for the host to be resolved again, so that
+/// addresses that went away are dropped.
+struct AddrConnector {
+    addr: SocketAddr,
+    inner: DefaultConnector,
+    reresolve: Arc<Reresolve>,
+    dialed: bool,
+}
+
+impl Service<Uri> for AddrConnector {
+    type Response = <DefaultConnector as Service<Uri>>::Response;
+    type Error = <DefaultConnector as Service<Uri>>::Error;
+    type Future = <DefaultConnector as Service<Uri>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let mut parts = uri.into_parts();
+        parts.authority = Some(
+            self.addr
+                .to_string()
+                .parse()
+                .expect("socket address is a valid authority"),
+        );
+        let uri = Uri::from_parts(parts).expect("replacing the authority keeps the uri valid");
+
+        if self.dialed {
+            self.reresolve.request();
+        }
+        self.dialed = true;
+
+        let connect = self.inner.call(uri);
+        let reresolve = self.reresolve.clone();
+        Box::pin(async move {
+            let res = connect.await;
+            if res.is_err() {
+                reresolve.request();
+            }
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::dns::StaticResolver;
+    use futures_util::{future::poll_fn, stream, task::noop_waker_ref};
+    use tokio::timer::Timeout;
+
+    fn closed_port() -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[test]
+    fn dns_discover_diffs_resolutions() {
+        let endpoint = Endpoint::from_static("http://example.com:50051");
+        let mut discover = DnsDiscover::new(
+            endpoint,
+            StaticResolver::new(),
+            RETRY_INTERVAL,
+            Connectivity::new(),
+        );
+
+        let a = "10.0.0.1".parse().unwrap();
+        let b = "10.0.0.2".parse().unwrap();
+        let c = "10.0.0.3".parse().unwrap();
+        let addr = |ip| SocketAddr::new(ip, 50051);
+
+        discover.update(vec![a, b]);
+        discover.changes.clear();
+
+        discover.update(vec![b, c]);
+        let changes = discover
+            .changes
+            .drain(..)
+            .map(|change| match change {
+                Change::Insert(addr, ()) => (true, addr),
+                Change::Remove(addr) => (false, addr),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(changes, vec![(false, addr(a)), (true, addr(c))]);
+    }
+
+    #[tokio::test]
+    async fn service_list_does_not_wait_for_connects() {
+        let failing = Endpoint::from_shared(format!("http://127.0.0.1:{}", closed_port())).unwrap();
+        let unroutable = Endpoint::from_static("http://10.255.255.1:50051");
+
+        let mut discover = ServiceList::new(vec![failing, unroutable], Connectivity::new());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut poll = || match Pin::new(&mut discover).poll_discover(&mut cx) {
+            Poll::Ready(Ok(Change::Insert(key, _))) => Some(key),
+            Poll::Ready(Ok(Change::Remove(key))) => panic!("unexpected remove of {}", key),
+            Poll::Ready(Err(error)) => panic!("discover failed: {}", error),
+            Poll::Pending => None,
+        };
+
+        assert_eq!(poll(), Some(0));
+        assert_eq!(poll(), Some(1));
+        assert_eq!(poll(), None);
+    }
+
+    #[tokio::test]
+    async fn dynamic_stream_does_not_wait_for_connects() {
+        let failing = Endpoint::from_shared(format!("http://127.0.0.1:{}", closed_port())).unwrap();
+        let changes = stream::iter(vec![Change::Insert(1, failing), Change::Remove(1)]);
+
+        let mut discover = DynamicServiceStream::new(changes, Connectivity::new());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut poll = || match Pin::new(&mut discover).poll_discover(&mut cx) {
+            Poll::Ready(Ok(Change::Insert(key, _))) => Some((true, key)),
+            Poll::Ready(Ok(Change::Remove(key))) => Some((false, key)),
+            Poll::Ready(Err(error)) => panic!("discover failed: {}", error),
+            Poll::Pending => None,
+        };
+
+        assert_eq!(poll(), Some((true, 1)));
+        assert_eq!(poll(), Some((false, 1)));
+        assert_eq!(poll(), None);
+    }
+
+    #[tokio::test]
+    async fn dns_discover_does_not_wait_for_connects() {
+        let unroutable = "10.255.255.1".parse().unwrap();
+        let resolver = StaticResolver::new();
+        resolver.set("example.com", vec![unroutable]);
+
+        let endpoint = Endpoint::from_static("http://example.com:50051");
+        let mut discover = DnsDiscover::new(
+            endpoint,
+            resolver,
+            Duration::from_secs(60),
+            Connectivity::new(),
+        );
+
+        let change = poll_fn(|cx| Pin::new(&mut discover).poll_discover(cx));
+        let change = Timeout::new(change, Duration::from_secs(1))
+            .await
+            .expect("insert waited for a connect")
+            .unwrap();
+
+        match change {
+            Change::Insert(addr, _) => assert_eq!(addr, SocketAddr::new(unroutable, 50051)),
+            Change::Remove(addr) => panic!("unexpected remove of {}", addr),
+        }
+    }
+
+    #[tokio::test]
+    async fn addr_connector_asks_to_reresolve() {
+        let reresolve = Arc::new(Reresolve::default());
+        let endpoint = Endpoint::from_static("http://example.com:50051");
+        let mut connector = AddrConnector {
+            addr: SocketAddr::from(([127, 0, 0, 1], closed_port())),
+            inner: DefaultConnector::new(&endpoint),
+            reresolve: reresolve.clone(),
+            dialed: false,
+        };
+        let uri = endpoint.uri.clone();
+
+        let connect = connector.call(uri.clone());
+        assert!(!reresolve.requested.load(Ordering::SeqCst));
+
+        // Failing to connect asks for a resolution.
+        assert!(connect.await.is_err());
+        assert!(reresolve.requested.swap(false, Ordering::SeqCst));
+
+        // So does dialing again after a connection was lost.
+        let _connect = connector.call(uri);
+        assert!(reresolve.requested.load(Ordering::SeqCst));
+    }
+}
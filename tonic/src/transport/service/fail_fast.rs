@@ -0,0 +1,122 @@
+use super::{either::Either, Connectivity};
+use crate::{transport::channel::ConnectivityState, Code, Status};
+use futures_util::future::{self, Ready};
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+/// Fails requests that wait for a connection while every connection of the
+/// channel is in `TransientFailure`, instead of holding them until one
+/// reconnects.
+///
+/// Connections that back off stay not ready, so that balancers route around
+/// them. Only once none is left to route to are waiting requests failed with
+/// `Code::Unavailable`. With `wait_for_ready` requests are held instead.
+#[derive(Debug)]
+pub(crate) struct FailFast<S> {
+    inner: S,
+    connectivity: Connectivity,
+    wait_for_ready: bool,
+    failing: bool,
+}
+
+impl<S> FailFast<S> {
+    pub(crate) fn new(inner: S, connectivity: Connectivity, wait_for_ready: bool) -> Self {
+        FailFast {
+            inner,
+            connectivity,
+            wait_for_ready,
+            failing: false,
+        }
+    }
+}
+
+impl<S, Request> Service<Request> for FailFast<S>
+where
+    S: Service<Request, Error = crate::Error>,
+{
+    type Response = S::Response;
+    type Error = crate::Error;
+    type Future = Either<S::Future, Ready<Result<S::Response, crate::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.failing = false;
+
+        match self.inner.poll_ready(cx) {
+            Poll::Pending
+                if !self.wait_for_ready
+                    && self.connectivity.get() == ConnectivityState::TransientFailure =>
+            {
+                self.failing = true;
+                Poll::Ready(Ok(()))
+            }
+            poll => poll,
+        }
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        if self.failing {
+            self.failing = false;
+            let status = Status::new(Code::Unavailable, "connection is in transient failure");
+            return Either::B(future::err(status.into()));
+        }
+
+        Either::A(self.inner.call(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        body::BoxBody,
+        client::GrpcService,
+        transport::{channel::ConnectivityState, Endpoint},
+        Code, Status,
+    };
+    use futures_util::future::poll_fn;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn requests_to_closed_port_fail() {
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+
+        let mut channel = Endpoint::from_shared(format!("http://127.0.0.1:{}", port))
+            .unwrap()
+            .reconnect_backoff(Duration::from_secs(60), Duration::from_secs(60))
+            .connect_lazy();
+
+        poll_fn(|cx| GrpcService::poll_ready(&mut channel, cx))
+            .await
+            .unwrap();
+        let error = GrpcService::call(&mut channel, http::Request::new(BoxBody::empty()))
+            .await
+            .unwrap_err();
+
+        assert_eq!(Status::from_error(&error).code(), Code::Unavailable);
+        assert_eq!(channel.state(), ConnectivityState::TransientFailure);
+    }
+
+    #[tokio::test]
+    async fn wait_for_ready_holds_requests() {
+        use tokio::timer::Timeout;
+
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+
+        let mut channel = Endpoint::from_shared(format!("http://127.0.0.1:{}", port))
+            .unwrap()
+            .reconnect_backoff(Duration::from_secs(60), Duration::from_secs(60))
+            .wait_for_ready(true)
+            .connect_lazy();
+
+        let ready = poll_fn(|cx| GrpcService::poll_ready(&mut channel, cx));
+        assert!(Timeout::new(ready, Duration::from_millis(500))
+            .await
+            .is_err());
+        assert_eq!(channel.state(), ConnectivityState::TransientFailure);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{body::BoxBody, Code, Status};
+use http::{Request, Response};
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::timer::Timeout;
+use tower::{layer::Layer, Service};
+
+/// Enforces a per-request time budget on the wrapped service.
+///
+/// Unlike tower's `Timeout`, expiring the budget is not surfaced as a service
+/// error, which would reset the stream, but as a regular gRPC response with a
+/// `DeadlineExceeded` status.
+#[derive(Debug, Clone)]
+pub(crate) struct GrpcTimeout<S> {
+    inner: S,
+    timeout: Duration,
+}
+
+impl<S> GrpcTimeout<S> {
+    pub(crate) fn new(inner: S, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for GrpcTimeout<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        ResponseFuture {
+            inner: Timeout::new(self.inner.call(req), self.timeout),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct GrpcTimeoutLayer {
+    timeout: Duration,
+}
+
+impl GrpcTimeoutLayer {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl<S> Layer<S> for GrpcTimeoutLayer {
+    type Service = GrpcTimeout<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcTimeout::new(inner, self.timeout)
+    }
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub(crate) struct ResponseFuture<F> {
+    #[pin]
+    inner: Timeout<F>,
+}
+
+impl<F, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<BoxBody>, E>>,
+{
+    type Output = Result<Response<BoxBody>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().inner.poll(cx) {
+            Poll::Ready(Ok(res)) => Poll::Ready(res),
+            Poll::Ready(Err(_elapsed)) => Poll::Ready(Ok(deadline_exceeded())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn deadline_exceeded() -> Response<BoxBody> {
+    let status = Status::new(Code::DeadlineExceeded, "Timeout expired");
+
+    let mut response = Response::new(BoxBody::empty());
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::header::HeaderValue::from_static("application/grpc"),
+    );
+    status.add_header(response.headers_mut()).unwrap();
+
+    response
+}
@@ -0,0 +1,646 @@
+use super::{
+    load::ResponseBody,
+    retry::{attempt_request, RetryFuture},
+};
+use crate::{
+    body::{BoxBody, BytesBuf},
+    client::grpc::Unary,
+    request::try_parse_grpc_timeout,
+    Code, Status,
+};
+use bytes::{Bytes, IntoBuf};
+use futures_util::future::poll_fn;
+use http::{request::Parts, Extensions, HeaderMap, Request, Response};
+use http_body::Body as HttpBody;
+use std::{
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+use tokio::timer::delay_for;
+use tower_service::Service;
+use tracing::debug;
+
+/// The most attempts the gRPC hedging spec allows.
+const MAX_ATTEMPTS: usize = 5;
+
+/// Configures hedging of unary requests on a [`Channel`], following the
+/// [gRPC retry design].
+///
+/// The request is sent, and whenever no response arrived after the
+/// [`hedging_delay`] it is sent again, up to [`max_attempts`] times in total.
+/// On a balanced channel the copies are spread across endpoints like any
+/// other request. The first successful response wins and the other attempts
+/// are cancelled.
+///
+/// An attempt failing with one of the [`non_fatal_codes`] triggers the next
+/// one right away, while any other failure is returned to the caller. Only
+/// unary calls are hedged, streaming calls are sent once.
+///
+/// ```
+/// # use tonic::transport::channel::HedgingPolicy;
+/// # use tonic::Code;
+/// # use std::time::Duration;
+/// let policy = HedgingPolicy::new()
+///     .max_attempts(3)
+///     .hedging_delay(Duration::from_millis(20))
+///     .non_fatal_codes(vec![Code::Unavailable]);
+/// ```
+///
+/// [`Channel`]: struct.Channel.html
+/// [gRPC retry design]: https://github.com/grpc/proposal/blob/master/A6-client-retries.md
+/// [`hedging_delay`]: #method.hedging_delay
+/// [`max_attempts`]: #method.max_attempts
+/// [`non_fatal_codes`]: #method.non_fatal_codes
+#[derive(Debug, Clone)]
+pub struct HedgingPolicy {
+    max_attempts: usize,
+    hedging_delay: Duration,
+    non_fatal_codes: Vec<Code>,
+}
+
+impl HedgingPolicy {
+    /// Create a policy that sends up to two attempts, 50 milliseconds apart.
+    pub fn new() -> Self {
+        HedgingPolicy {
+            max_attempts: 2,
+            hedging_delay: Duration::from_millis(50),
+            non_fatal_codes: Vec::new(),
+        }
+    }
+
+    /// Set how many attempts are sent at most, including the first one.
+    ///
+    /// Values above 5 are capped to 5, as the spec requires.
+    pub fn max_attempts(self, max_attempts: usize) -> Self {
+        HedgingPolicy {
+            max_attempts: max_attempts.min(MAX_ATTEMPTS),
+            ..self
+        }
+    }
+
+    /// Set how long to wait for a response before sending the next attempt.
+    ///
+    /// A delay of zero sends all attempts at once.
+    pub fn hedging_delay(self, delay: Duration) -> Self {
+        HedgingPolicy {
+            hedging_delay: delay,
+            ..self
+        }
+    }
+
+    /// Set the codes that trigger the next attempt right away instead of
+    /// failing the call. Default is none.
+    pub fn non_fatal_codes(self, codes: Vec<Code>) -> Self {
+        HedgingPolicy {
+            non_fatal_codes: codes,
+            ..self
+        }
+    }
+}
+
+impl Default for HedgingPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The hedging state shared by all clones of a channel.
+#[derive(Debug, Clone)]
+pub(crate) struct Hedge {
+    policy: Arc<HedgingPolicy>,
+}
+
+impl Hedge {
+    pub(crate) fn new(policy: HedgingPolicy) -> Self {
+        Hedge {
+            policy: Arc::new(policy),
+        }
+    }
+
+    /// Send `request` on `svc`, which must be ready, hedging it on other
+    /// clones of `svc` if it is a unary call.
+    pub(crate) fn call<S>(&self, svc: &mut S, request: Request<BoxBody>) -> RetryFuture
+    where
+        S: Service<Request<BoxBody>, Response = Response<ResponseBody>, Error = crate::Error>
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        if self.policy.max_attempts < 2 || request.extensions().get::<Unary>().is_none() {
+            return Box::pin(svc.call(request));
+        }
+
+        let (mut parts, body) = request.into_parts();
+        let deadline = match try_parse_grpc_timeout(&parts.headers) {
+            Ok(timeout) => timeout.map(|timeout| Instant::now() + timeout),
+            Err(_) => None,
+        };
+
+        let buffered = Buffered::new(body);
+        let mut first = attempt_request(&parts, buffered.body(), 0, None);
+        // Only the first attempt gets the extensions, they cannot be cloned.
+        *first.extensions_mut() = mem::replace(&mut parts.extensions, Extensions::new());
+        let first = svc.call(first);
+
+        Box::pin(run(
+            svc.clone(),
+            self.clone(),
+            parts,
+            buffered,
+            deadline,
+            first,
+        ))
+    }
+}
+
+/// What a finished attempt means for the call.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// The response is handed to the caller.
+    Commit,
+    /// The next attempt is sent right away.
+    NonFatal,
+}
+
+fn outcome(policy: &HedgingPolicy, res: &Result<Response<ResponseBody>, crate::Error>) -> Outcome {
+    let code = match res {
+        Ok(response) => match Status::from_header_map(response.headers()) {
+            // A trailers-only response, the call failed before it began.
+            Some(status) => status.code(),
+            None => return Outcome::Commit,
+        },
+        Err(_) => Code::Unavailable,
+    };
+
+    if code != Code::Ok && policy.non_fatal_codes.contains(&code) {
+        Outcome::NonFatal
+    } else {
+        Outcome::Commit
+    }
+}
+
+enum Event {
+    Done(usize, Result<Response<ResponseBody>, crate::Error>),
+    Hedge,
+}
+
+async fn run<S>(
+    mut svc: S,
+    hedge: Hedge,
+    parts: Parts,
+    buffered: Buffered,
+    deadline: Option<Instant>,
+    first: S::Future,
+) -> Result<Response<ResponseBody>, crate::Error>
+where
+    S: Service<Request<BoxBody>, Response = Response<ResponseBody>, Error = crate::Error>,
+{
+    let policy = &hedge.policy;
+    let mut attempts = vec![Box::pin(first)];
+    let mut sent = 1;
+    let mut last = None;
+    let mut delay = delay_for(policy.hedging_delay);
+
+    loop {
+        let event = poll_fn(|cx| {
+            for (i, attempt) in attempts.iter_mut().enumerate() {
+                if let Poll::Ready(res) = attempt.as_mut().poll(cx) {
+                    return Poll::Ready(Ok(Event::Done(i, res)));
+                }
+            }
+
+            if sent < policy.max_attempts && Pin::new(&mut delay).poll(cx).is_ready() {
+                futures_util::ready!(svc.poll_ready(cx))?;
+                return Poll::Ready(Ok(Event::Hedge));
+            }
+
+            Poll::Pending
+        })
+        .await?;
+
+        match event {
+            Event::Hedge => {
+                let timeout = match deadline {
+                    Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                        Some(timeout) if timeout > Duration::from_secs(0) => Some(timeout),
+                        // Out of time, wait for the attempts in flight.
+                        _ => {
+                            sent = policy.max_attempts;
+                            // With nothing in flight the last failure is
+                            // the answer, otherwise it is kept for when the
+                            // remaining attempts fail too.
+                            if attempts.is_empty() {
+                                if let Some(res) = last.take() {
+                                    return res;
+                                }
+                            }
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+
+                debug!("hedging request, attempt {}", sent + 1);
+                let request = attempt_request(&parts, buffered.body(), sent, timeout);
+                attempts.push(Box::pin(svc.call(request)));
+                sent += 1;
+                delay.reset(Instant::now() + policy.hedging_delay);
+            }
+            Event::Done(i, res) => {
+                // Dropping the attempts still in flight cancels them.
+                if outcome(policy, &res) == Outcome::Commit {
+                    return res;
+                }
+
+                attempts.remove(i);
+
+                if sent < policy.max_attempts {
+                    delay.reset(Instant::now());
+                } else if attempts.is_empty() {
+                    return res;
+                }
+
+                last = Some(res);
+            }
+        }
+    }
+}
+
+/// A request body that is recorded as it is sent, so that any number of
+/// attempts can send it concurrently.
+#[derive(Clone)]
+struct Buffered {
+    shared: Arc<Mutex<Shared>>,
+}
+
+struct Shared {
+    body: BoxBody,
+    chunks: Vec<Bytes>,
+    /// The end of the body, or the error it failed with.
+    end: Option<Option<Status>>,
+    /// Attempts waiting for the attempt polling `body` to get data.
+    waiting: Vec<Waker>,
+}
+
+impl Buffered {
+    fn new(body: BoxBody) -> Self {
+        let shared = Shared {
+            body,
+            chunks: Vec::new(),
+            end: None,
+            waiting: Vec::new(),
+        };
+
+        Buffered {
+            shared: Arc::new(Mutex::new(shared)),
+        }
+    }
+
+    fn body(&self) -> BufferedBody {
+        BufferedBody {
+            shared: self.shared.clone(),
+            pos: 0,
+        }
+    }
+}
+
+struct BufferedBody {
+    shared: Arc<Mutex<Shared>>,
+    pos: usize,
+}
+
+impl HttpBody for BufferedBody {
+    type Data = BytesBuf;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+        let mut shared = me.shared.lock().unwrap();
+
+        if me.pos < shared.chunks.len() {
+            me.pos += 1;
+            return Poll::Ready(Some(Ok(shared.chunks[me.pos - 1].clone().into_buf())));
+        }
+
+        match &shared.end {
+            Some(None) => return Poll::Ready(None),
+            Some(Some(status)) => return Poll::Ready(Some(Err(status.clone()))),
+            None => {}
+        }
+
+        let res = match Pin::new(&mut shared.body).poll_data(cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => {
+                if !shared.waiting.iter().any(|w| w.will_wake(cx.waker())) {
+                    shared.waiting.push(cx.waker().clone());
+                }
+                return Poll::Pending;
+            }
+        };
+
+        for waker in shared.waiting.drain(..) {
+            waker.wake();
+        }
+
+        match res {
+            Some(Ok(chunk)) => {
+                let bytes = chunk.get_ref().slice_from(chunk.position() as usize);
+                shared.chunks.push(bytes.clone());
+                me.pos += 1;
+                Poll::Ready(Some(Ok(bytes.into_buf())))
+            }
+            Some(Err(status)) => {
+                shared.end = Some(Some(status.clone()));
+                Poll::Ready(Some(Err(status)))
+            }
+            None => {
+                shared.end = Some(None);
+                Poll::Ready(None)
+            }
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        // gRPC requests never have trailers.
+        Poll::Ready(Ok(None))
+    }
+}
+
+impl Drop for BufferedBody {
+    fn drop(&mut self) {
+        // The inner body only wakes the attempt that polled it last, which
+        // may be this one. The others are woken so that one of them takes
+        // over polling it.
+        if let Ok(mut shared) = self.shared.lock() {
+            for waker in shared.waiting.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::GRPC_TIMEOUT_HEADER;
+    use futures_util::task::{self, ArcWake};
+    use http::HeaderValue;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn response(status: Option<Code>) -> Result<Response<ResponseBody>, crate::Error> {
+        let mut response = Response::new(ResponseBody::empty());
+        if let Some(code) = status {
+            response
+                .headers_mut()
+                .insert("grpc-status", HeaderValue::from(code as u64));
+        }
+        Ok(response)
+    }
+
+    #[test]
+    fn non_fatal_codes_trigger_next_attempt() {
+        let policy = HedgingPolicy::new().non_fatal_codes(vec![Code::Unavailable]);
+
+        assert_eq!(outcome(&policy, &response(None)), Outcome::Commit);
+        assert_eq!(outcome(&policy, &response(Some(Code::Ok))), Outcome::Commit);
+        assert_eq!(
+            outcome(&policy, &response(Some(Code::Unavailable))),
+            Outcome::NonFatal
+        );
+        assert_eq!(
+            outcome(&policy, &response(Some(Code::NotFound))),
+            Outcome::Commit
+        );
+        assert_eq!(
+            outcome(&policy, &Err("connection reset".into())),
+            Outcome::NonFatal
+        );
+    }
+
+    struct Once(Option<Bytes>);
+
+    impl HttpBody for Once {
+        type Data = BytesBuf;
+        type Error = Status;
+
+        fn poll_data(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            Poll::Ready(self.0.take().map(|bytes| Ok(bytes.into_buf())))
+        }
+
+        fn poll_trailers(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+            Poll::Ready(Ok(None))
+        }
+    }
+
+    #[tokio::test]
+    async fn attempts_share_the_body() {
+        let buffered = Buffered::new(BoxBody::new(Once(Some(Bytes::from("hello")))));
+
+        let mut first = buffered.body();
+        let mut second = buffered.body();
+
+        let chunk = poll_fn(|cx| Pin::new(&mut first).poll_data(cx)).await;
+        assert_eq!(chunk.unwrap().unwrap().into_inner(), Bytes::from("hello"));
+
+        let chunk = poll_fn(|cx| Pin::new(&mut second).poll_data(cx)).await;
+        assert_eq!(chunk.unwrap().unwrap().into_inner(), Bytes::from("hello"));
+
+        assert!(poll_fn(|cx| Pin::new(&mut second).poll_data(cx))
+            .await
+            .is_none());
+        assert!(poll_fn(|cx| Pin::new(&mut first).poll_data(cx))
+            .await
+            .is_none());
+    }
+
+    /// A body that never yields.
+    struct Stalled;
+
+    impl HttpBody for Stalled {
+        type Data = BytesBuf;
+        type Error = Status;
+
+        fn poll_data(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            Poll::Pending
+        }
+
+        fn poll_trailers(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+            Poll::Ready(Ok(None))
+        }
+    }
+
+    #[derive(Default)]
+    struct Woken(AtomicUsize);
+
+    impl ArcWake for Woken {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn dropping_the_polling_attempt_wakes_the_others() {
+        let buffered = Buffered::new(BoxBody::new(Stalled));
+
+        let mut first = buffered.body();
+        let mut second = buffered.body();
+
+        let woken = Arc::new(Woken::default());
+        let waker = task::waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut first).poll_data(&mut cx).is_pending());
+
+        // The second attempt polls the body last, so only it would be woken
+        // by the body.
+        let mut cx = Context::from_waker(task::noop_waker_ref());
+        assert!(Pin::new(&mut second).poll_data(&mut cx).is_pending());
+        assert_eq!(woken.0.load(Ordering::SeqCst), 0);
+
+        drop(second);
+        assert_eq!(woken.0.load(Ordering::SeqCst), 1);
+    }
+
+    /// Answers the `n`th request with the `n`th response of its script,
+    /// after the given delay.
+    #[derive(Clone)]
+    struct Mock {
+        script: Arc<Vec<(Duration, Option<Code>)>>,
+        calls: Arc<Mutex<Vec<(Instant, HeaderMap)>>>,
+        cancelled: Arc<AtomicUsize>,
+    }
+
+    impl Mock {
+        fn new(script: Vec<(Duration, Option<Code>)>) -> Self {
+            Mock {
+                script: Arc::new(script),
+                calls: Arc::new(Mutex::new(Vec::new())),
+                cancelled: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+    }
+
+    /// Counts the responses that were dropped before they were ready.
+    struct Cancel(Option<Arc<AtomicUsize>>);
+
+    impl Drop for Cancel {
+        fn drop(&mut self) {
+            if let Some(cancelled) = self.0.take() {
+                cancelled.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    impl Service<Request<BoxBody>> for Mock {
+        type Response = Response<ResponseBody>;
+        type Error = crate::Error;
+        type Future = RetryFuture;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+            let mut calls = self.calls.lock().unwrap();
+            let (delay, code) = self.script[calls.len()];
+            calls.push((Instant::now(), request.headers().clone()));
+
+            let cancel = Cancel(Some(self.cancelled.clone()));
+            Box::pin(async move {
+                let mut cancel = cancel;
+                delay_for(delay).await;
+                cancel.0 = None;
+                response(code)
+            })
+        }
+    }
+
+    fn unary(timeout: Option<&'static str>) -> Request<BoxBody> {
+        let mut request = Request::new(BoxBody::new(Once(Some(Bytes::from("hello")))));
+        request.extensions_mut().insert(Unary);
+        if let Some(timeout) = timeout {
+            request
+                .headers_mut()
+                .insert(GRPC_TIMEOUT_HEADER, HeaderValue::from_static(timeout));
+        }
+        request
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[tokio::test]
+    async fn hedges_after_delay() {
+        let hedge = Hedge::new(HedgingPolicy::new().hedging_delay(ms(30)));
+        let mut svc = Mock::new(vec![(ms(500), None), (ms(0), None)]);
+
+        let start = Instant::now();
+        hedge.call(&mut svc, unary(None)).await.unwrap();
+        assert!(start.elapsed() < ms(500));
+
+        let calls = svc.calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[1].0 - calls[0].0 >= ms(30));
+        assert_eq!(calls[1].1["grpc-previous-rpc-attempts"], "1");
+    }
+
+    #[tokio::test]
+    async fn first_success_cancels_the_rest() {
+        let policy = HedgingPolicy::new().max_attempts(3).hedging_delay(ms(0));
+        let hedge = Hedge::new(policy);
+        let mut svc = Mock::new(vec![(ms(500), None), (ms(10), None), (ms(500), None)]);
+
+        let res = hedge.call(&mut svc, unary(None)).await.unwrap();
+        assert!(Status::from_header_map(res.headers()).is_none());
+
+        assert_eq!(svc.calls.lock().unwrap().len(), 3);
+        assert_eq!(svc.cancelled.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn returns_last_failure_past_deadline() {
+        let policy = HedgingPolicy::new()
+            .max_attempts(3)
+            .hedging_delay(ms(1000))
+            .non_fatal_codes(vec![Code::Unavailable]);
+        let hedge = Hedge::new(policy);
+        let mut svc = Mock::new(vec![
+            (ms(10), Some(Code::Unavailable)),
+            (ms(50), Some(Code::Unavailable)),
+            (ms(0), None),
+        ]);
+
+        // The first failure is retried right away, the second one comes back
+        // after the deadline, so no third attempt is made.
+        let res = hedge.call(&mut svc, unary(Some("30m"))).await.unwrap();
+        let status = Status::from_header_map(res.headers()).unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
+
+        let calls = svc.calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[1].1.contains_key(GRPC_TIMEOUT_HEADER));
+    }
+}
@@ -0,0 +1,238 @@
+use crate::transport::server::Connected;
+use bytes::BytesMut;
+use futures_core::Stream;
+use http::Uri;
+use std::{
+    cmp, fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+};
+use tower_service::Service;
+
+/// How many bytes may be written to one direction of a pipe before the
+/// writer has to wait for the reader to catch up.
+const MAX_BUF_SIZE: usize = 64 * 1024;
+
+/// Create a connector and the matching stream of server side connections.
+///
+/// Every connection made with the connector hands one end of a new in-memory
+/// pipe to the stream. The stream ends once the connector has been dropped.
+pub(crate) fn in_process() -> (
+    InProcessConnector,
+    impl Stream<Item = Result<DuplexStream, crate::Error>>,
+) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let incoming = async_stream::stream! {
+        while let Some(io) = rx.recv().await {
+            yield Ok(io);
+        }
+    };
+
+    (InProcessConnector { tx }, incoming)
+}
+
+/// Connects to a server fed by the stream returned from [`in_process`].
+pub(crate) struct InProcessConnector {
+    tx: mpsc::UnboundedSender<DuplexStream>,
+}
+
+impl Service<Uri> for InProcessConnector {
+    type Response = DuplexStream;
+    type Error = crate::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, _: Uri) -> Self::Future {
+        let (client, server) = duplex(MAX_BUF_SIZE);
+
+        let res: Result<_, crate::Error> = self
+            .tx
+            .try_send(server)
+            .map(|_| client)
+            .map_err(|_| "in-process server is no longer running".into());
+
+        Box::pin(async move { res })
+    }
+}
+
+impl fmt::Debug for InProcessConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InProcessConnector").finish()
+    }
+}
+
+/// Create a pair of connected in-memory streams.
+///
+/// Data written to one end can be read from the other.
+pub(crate) fn duplex(max_buf_size: usize) -> (DuplexStream, DuplexStream) {
+    let one = Arc::new(Mutex::new(Pipe::new(max_buf_size)));
+    let two = Arc::new(Mutex::new(Pipe::new(max_buf_size)));
+
+    let a = DuplexStream {
+        read: one.clone(),
+        write: two.clone(),
+    };
+    let b = DuplexStream {
+        read: two,
+        write: one,
+    };
+
+    (a, b)
+}
+
+/// One end of an in-memory pipe created with [`duplex`].
+pub(crate) struct DuplexStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+impl Connected for DuplexStream {}
+
+/// A single direction of a [`DuplexStream`].
+struct Pipe {
+    buf: BytesMut,
+    max_buf_size: usize,
+    is_closed: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl Pipe {
+    fn new(max_buf_size: usize) -> Self {
+        Pipe {
+            buf: BytesMut::new(),
+            max_buf_size,
+            is_closed: false,
+            read_waker: None,
+            write_waker: None,
+        }
+    }
+
+    fn close(&mut self) {
+        self.is_closed = true;
+
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl AsyncRead for DuplexStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.read.lock().unwrap();
+
+        if !pipe.buf.is_empty() {
+            let n = cmp::min(buf.len(), pipe.buf.len());
+            buf[..n].copy_from_slice(&pipe.buf[..n]);
+            pipe.buf.advance(n);
+
+            if let Some(waker) = pipe.write_waker.take() {
+                waker.wake();
+            }
+
+            Poll::Ready(Ok(n))
+        } else if pipe.is_closed {
+            Poll::Ready(Ok(0))
+        } else {
+            pipe.read_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl AsyncWrite for DuplexStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.write.lock().unwrap();
+
+        if pipe.is_closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        let available = pipe.max_buf_size - pipe.buf.len();
+        if available == 0 {
+            pipe.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let n = cmp::min(buf.len(), available);
+        pipe.buf.extend_from_slice(&buf[..n]);
+
+        if let Some(waker) = pipe.read_waker.take() {
+            waker.wake();
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.write.lock().unwrap().close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        // Let the other end observe EOF and fail its pending writes.
+        self.read.lock().unwrap().close();
+        self.write.lock().unwrap().close();
+    }
+}
+
+impl fmt::Debug for DuplexStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DuplexStream").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn duplex_roundtrip() {
+        let (mut a, mut b) = duplex(8);
+
+        let data = b"hello from the other side".to_vec();
+        let expected = data.clone();
+
+        let writer = async move {
+            a.write_all(&data).await.unwrap();
+        };
+        let reader = async move {
+            let mut read = Vec::new();
+            b.read_to_end(&mut read).await.unwrap();
+            read
+        };
+
+        let ((), read) = futures_util::future::join(writer, reader).await;
+        assert_eq!(read, expected);
+    }
+}
@@ -1,7 +1,12 @@
+use crate::transport::server::ConnectInfo;
+use futures_util::pin_mut;
+use std::fmt;
+use std::future::Future;
 use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
 
 pub(in crate::transport) trait Io:
     AsyncRead + AsyncWrite + Send + Unpin + 'static
@@ -18,6 +23,12 @@ impl BoxedIo {
     }
 }
 
+impl fmt::Debug for BoxedIo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxedIo").finish()
+    }
+}
+
 impl AsyncRead for BoxedIo {
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -45,3 +56,92 @@ impl AsyncWrite for BoxedIo {
         Pin::new(&mut self.0).poll_shutdown(cx)
     }
 }
+
+/// A server connection along with what is known about its peer.
+///
+/// Once a value is broadcast on `close`, every read and write fails, so that
+/// `hyper` drops the connection even if its streams never finish.
+pub(crate) struct ServerIo {
+    io: BoxedIo,
+    info: ConnectInfo,
+    close: watch::Receiver<()>,
+    is_closed: bool,
+}
+
+impl ServerIo {
+    pub(in crate::transport) fn new(
+        io: BoxedIo,
+        info: ConnectInfo,
+        close: watch::Receiver<()>,
+    ) -> Self {
+        ServerIo {
+            io,
+            info,
+            close,
+            is_closed: false,
+        }
+    }
+
+    pub(in crate::transport) fn info(&self) -> &ConnectInfo {
+        &self.info
+    }
+
+    fn poll_closed(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        if !self.is_closed {
+            let recv = self.close.recv_ref();
+            pin_mut!(recv);
+            // The sender going away without a broadcast is not a close.
+            self.is_closed = match recv.poll(cx) {
+                Poll::Ready(Some(_)) => true,
+                Poll::Ready(None) | Poll::Pending => false,
+            };
+        }
+
+        if self.is_closed {
+            Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "server closed the connection",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Debug for ServerIo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerIo")
+            .field("info", &self.info)
+            .finish()
+    }
+}
+
+impl AsyncRead for ServerIo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_closed(cx)?;
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ServerIo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_closed(cx)?;
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,604 @@
+//! HTTP/2 keepalive pings, behind the `keepalive` feature.
+//!
+//! The pinned `hyper` does not expose PING frames, so keepalive is
+//! implemented on the connection's byte stream instead. Outbound frames are
+//! tracked so that pings are only ever injected between two frames written
+//! by `h2`, and inbound frames are inspected so that acknowledgements of our
+//! own pings are filtered out before `h2` sees them. Nothing else about the
+//! connection is touched, pings sent by the peer are left to `h2`.
+//!
+//! This goes away once `hyper` can send pings itself.
+
+use super::BoxedIo;
+#[cfg(feature = "keepalive")]
+use bytes::{BufMut, BytesMut};
+use std::time::Duration;
+#[cfg(feature = "keepalive")]
+use std::{
+    cmp, fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+#[cfg(feature = "keepalive")]
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    timer::{delay, Delay},
+};
+
+#[cfg(feature = "keepalive")]
+const PREFACE_LEN: usize = 24;
+#[cfg(feature = "keepalive")]
+const FRAME_HEADER_LEN: usize = 9;
+
+#[cfg(feature = "keepalive")]
+const FRAME_SETTINGS: u8 = 0x4;
+#[cfg(feature = "keepalive")]
+const FRAME_PING: u8 = 0x6;
+#[cfg(feature = "keepalive")]
+const FLAG_ACK: u8 = 0x1;
+
+/// The opaque data of the pings sent by tonic.
+#[cfg(feature = "keepalive")]
+const PING_PAYLOAD: [u8; 8] = *b"tonic-ka";
+
+#[cfg(feature = "keepalive")]
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Keepalive settings for one side of a connection.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct KeepAlive {
+    /// How often to send a PING frame, if at all.
+    pub(crate) interval: Option<Duration>,
+    /// How long to wait for the peer after sending a PING frame.
+    pub(crate) timeout: Option<Duration>,
+    /// Whether to keep pinging a connection that has seen no traffic since
+    /// the previous ping.
+    pub(crate) while_idle: bool,
+}
+
+impl KeepAlive {
+    /// Wrap the client side of a connection, leaving it as is if keepalive
+    /// is not enabled.
+    pub(crate) fn client(self, io: BoxedIo) -> BoxedIo {
+        self.wrap(io, true)
+    }
+
+    /// Wrap the server side of a connection, leaving it as is if keepalive
+    /// is not enabled.
+    pub(crate) fn server(self, io: BoxedIo) -> BoxedIo {
+        self.wrap(io, false)
+    }
+
+    #[cfg(feature = "keepalive")]
+    fn wrap(self, io: BoxedIo, is_client: bool) -> BoxedIo {
+        if self.interval.is_some() {
+            BoxedIo::new(KeepAliveIo::new(io, self, is_client))
+        } else {
+            io
+        }
+    }
+
+    #[cfg(not(feature = "keepalive"))]
+    fn wrap(self, io: BoxedIo, _is_client: bool) -> BoxedIo {
+        io
+    }
+}
+
+#[cfg(feature = "keepalive")]
+enum PingState {
+    /// Waiting for the interval to pass before sending the next ping.
+    Idle,
+    /// A ping was sent and nothing has been heard from the peer since.
+    AwaitingAck,
+}
+
+/// Tracks the frame boundaries of one direction of an HTTP/2 connection.
+#[cfg(feature = "keepalive")]
+struct Frames {
+    /// Bytes of the current frame, or of the preface, not yet seen.
+    remaining: usize,
+    header: [u8; FRAME_HEADER_LEN],
+    header_len: usize,
+}
+
+#[cfg(feature = "keepalive")]
+impl Frames {
+    fn new(preface: usize) -> Self {
+        Frames {
+            remaining: preface,
+            header: [0; FRAME_HEADER_LEN],
+            header_len: 0,
+        }
+    }
+
+    fn is_at_boundary(&self) -> bool {
+        self.remaining == 0 && self.header_len == 0
+    }
+
+    /// Track `data`, calling `on_head` with the head of every frame that
+    /// starts in it.
+    fn advance(&mut self, mut data: &[u8], mut on_head: impl FnMut(&FrameHead)) {
+        while !data.is_empty() {
+            if self.remaining > 0 {
+                let n = cmp::min(self.remaining, data.len());
+                self.remaining -= n;
+                data = &data[n..];
+                continue;
+            }
+
+            let n = cmp::min(FRAME_HEADER_LEN - self.header_len, data.len());
+            self.header[self.header_len..self.header_len + n].copy_from_slice(&data[..n]);
+            self.header_len += n;
+            data = &data[n..];
+
+            if self.header_len == FRAME_HEADER_LEN {
+                let head = FrameHead::parse(&self.header);
+                on_head(&head);
+                self.remaining = head.len;
+                self.header_len = 0;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "keepalive")]
+struct FrameHead {
+    len: usize,
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+}
+
+#[cfg(feature = "keepalive")]
+impl FrameHead {
+    fn parse(header: &[u8]) -> Self {
+        FrameHead {
+            len: (header[0] as usize) << 16 | (header[1] as usize) << 8 | header[2] as usize,
+            kind: header[3],
+            flags: header[4],
+            stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]])
+                & 0x7fff_ffff,
+        }
+    }
+}
+
+#[cfg(feature = "keepalive")]
+fn put_frame_header(buf: &mut BytesMut, len: usize, kind: u8, flags: u8, stream_id: u32) {
+    buf.reserve(FRAME_HEADER_LEN + len);
+    buf.put_slice(&(len as u32).to_be_bytes()[1..]);
+    buf.put_u8(kind);
+    buf.put_u8(flags);
+    buf.put_u32_be(stream_id);
+}
+
+/// An HTTP/2 connection that sends keepalive pings.
+#[cfg(feature = "keepalive")]
+pub(crate) struct KeepAliveIo<T> {
+    inner: T,
+    config: KeepAlive,
+
+    /// Outbound frame tracking, used to find where frames can be injected.
+    written: Frames,
+    /// Whether `h2` started writing its SETTINGS frame, which has to be the
+    /// first frame of the connection.
+    wrote_settings: bool,
+    /// Frames waiting for the next outbound frame boundary.
+    queued: BytesMut,
+    /// Injected frames that have been partially written.
+    injecting: BytesMut,
+    /// Whether injected frames were written since the last flush.
+    needs_flush: bool,
+
+    /// Inbound bytes that have not been handed to `h2` yet.
+    read_buf: BytesMut,
+    /// Inbound bytes, starting at `read_buf`, that can be handed to `h2`
+    /// without further inspection.
+    readable: usize,
+
+    ping_state: PingState,
+    timer: Option<Delay>,
+    /// Whether a frame was sent or received on a stream since the last
+    /// ping. Frames on the connection itself, such as PING acknowledgements,
+    /// do not count.
+    is_active: bool,
+}
+
+#[cfg(feature = "keepalive")]
+impl<T> KeepAliveIo<T> {
+    fn new(inner: T, config: KeepAlive, is_client: bool) -> Self {
+        let (written, readable) = if is_client {
+            (Frames::new(PREFACE_LEN), 0)
+        } else {
+            (Frames::new(0), PREFACE_LEN)
+        };
+
+        KeepAliveIo {
+            inner,
+            config,
+            written,
+            wrote_settings: false,
+            queued: BytesMut::new(),
+            injecting: BytesMut::new(),
+            needs_flush: false,
+            read_buf: BytesMut::new(),
+            readable,
+            ping_state: PingState::Idle,
+            timer: None,
+            is_active: false,
+        }
+    }
+
+    fn queue_ping(&mut self) {
+        put_frame_header(&mut self.queued, PING_PAYLOAD.len(), FRAME_PING, 0, 0);
+        self.queued.put_slice(&PING_PAYLOAD);
+    }
+
+    /// Drive the ping timer, queueing a ping when one is due.
+    fn poll_timer(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        let interval = match self.config.interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        let timeout = self.config.timeout.unwrap_or(DEFAULT_TIMEOUT);
+
+        loop {
+            let timer = self
+                .timer
+                .get_or_insert_with(|| delay(Instant::now() + interval));
+
+            if Pin::new(&mut *timer).poll(cx).is_pending() {
+                return Ok(());
+            }
+
+            match self.ping_state {
+                PingState::Idle if self.config.while_idle || self.is_active => {
+                    self.queue_ping();
+                    self.is_active = false;
+                    self.ping_state = PingState::AwaitingAck;
+                    timer.reset(Instant::now() + timeout);
+                }
+                PingState::Idle => timer.reset(Instant::now() + interval),
+                PingState::AwaitingAck => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "keepalive ping timed out",
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Record that something was heard from the peer, which answers any
+    /// outstanding ping.
+    fn on_peer_activity(&mut self) {
+        if let PingState::AwaitingAck = self.ping_state {
+            self.ping_state = PingState::Idle;
+            if let (Some(timer), Some(interval)) = (&mut self.timer, self.config.interval) {
+                timer.reset(Instant::now() + interval);
+            }
+        }
+    }
+
+    /// Inspect buffered inbound bytes, returning `false` once more bytes are
+    /// needed to make progress.
+    fn inspect(&mut self) -> bool {
+        if self.readable > 0 || self.read_buf.len() < FRAME_HEADER_LEN {
+            return false;
+        }
+
+        let head = FrameHead::parse(&self.read_buf[..FRAME_HEADER_LEN]);
+        let frame_len = FRAME_HEADER_LEN + head.len;
+
+        if head.stream_id != 0 {
+            self.is_active = true;
+        }
+
+        if head.kind == FRAME_PING && head.flags & FLAG_ACK == FLAG_ACK {
+            if self.read_buf.len() < frame_len {
+                return false;
+            }
+
+            if self.read_buf[FRAME_HEADER_LEN..frame_len] == PING_PAYLOAD[..] {
+                // Our own ping, `h2` does not know about it.
+                self.read_buf.advance(frame_len);
+                return true;
+            }
+        }
+
+        self.readable = frame_len;
+        true
+    }
+}
+
+#[cfg(feature = "keepalive")]
+impl<T: AsyncWrite + Unpin> KeepAliveIo<T> {
+    /// Write out queued frames, starting only at a frame boundary after the
+    /// SETTINGS frame of `h2`.
+    fn poll_inject(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.injecting.is_empty()
+            && !self.queued.is_empty()
+            && self.wrote_settings
+            && self.written.is_at_boundary()
+        {
+            self.injecting = self.queued.take();
+        }
+
+        while !self.injecting.is_empty() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.injecting) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => {
+                    self.injecting.advance(n);
+                    self.needs_flush = true;
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "keepalive")]
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for KeepAliveIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = &mut *self;
+
+        me.poll_timer(cx)?;
+
+        // Pings have to go out even if `h2` has nothing to write.
+        if let Poll::Ready(()) = me.poll_inject(cx)? {
+            if me.needs_flush && Pin::new(&mut me.inner).poll_flush(cx)?.is_ready() {
+                me.needs_flush = false;
+            }
+        }
+
+        loop {
+            if me.readable > 0 && !me.read_buf.is_empty() {
+                let n = cmp::min(cmp::min(me.readable, me.read_buf.len()), buf.len());
+                buf[..n].copy_from_slice(&me.read_buf[..n]);
+                me.read_buf.advance(n);
+                me.readable -= n;
+                return Poll::Ready(Ok(n));
+            }
+
+            if me.inspect() {
+                continue;
+            }
+
+            let mut chunk = [0; 8 * 1024];
+            let n = futures_util::ready!(Pin::new(&mut me.inner).poll_read(cx, &mut chunk))?;
+
+            if n == 0 {
+                // Hand over whatever is left so `h2` can report the EOF.
+                let n = cmp::min(me.read_buf.len(), buf.len());
+                buf[..n].copy_from_slice(&me.read_buf[..n]);
+                me.read_buf.advance(n);
+                return Poll::Ready(Ok(n));
+            }
+
+            me.read_buf.extend_from_slice(&chunk[..n]);
+            me.on_peer_activity();
+        }
+    }
+}
+
+#[cfg(feature = "keepalive")]
+impl<T: AsyncWrite + Unpin> AsyncWrite for KeepAliveIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = &mut *self;
+
+        futures_util::ready!(me.poll_inject(cx))?;
+
+        let n = futures_util::ready!(Pin::new(&mut me.inner).poll_write(cx, buf))?;
+
+        let (mut settings, mut on_stream) = (false, false);
+        me.written.advance(&buf[..n], |head| {
+            settings |= head.kind == FRAME_SETTINGS;
+            on_stream |= head.stream_id != 0;
+        });
+        me.wrote_settings |= settings;
+        me.is_active |= on_stream;
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let me = &mut *self;
+
+        futures_util::ready!(me.poll_inject(cx))?;
+        futures_util::ready!(Pin::new(&mut me.inner).poll_flush(cx))?;
+        me.needs_flush = false;
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let me = &mut *self;
+
+        futures_util::ready!(me.poll_inject(cx))?;
+        Pin::new(&mut me.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "keepalive")]
+impl<T> fmt::Debug for KeepAliveIo<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeepAliveIo")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "keepalive"))]
+mod tests {
+    use super::super::in_process::{duplex, DuplexStream};
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        timer::Timeout,
+    };
+
+    const FRAME_HEADERS: u8 = 0x1;
+
+    fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        put_frame_header(&mut buf, payload.len(), kind, flags, stream_id);
+        buf.put_slice(payload);
+        buf.to_vec()
+    }
+
+    /// Read the kind, flags and payload of the next frame sent to `io`.
+    async fn read_frame(io: &mut DuplexStream) -> (u8, u8, Vec<u8>) {
+        let mut header = [0; FRAME_HEADER_LEN];
+        io.read_exact(&mut header).await.unwrap();
+        let head = FrameHead::parse(&header);
+
+        let mut payload = vec![0; head.len];
+        io.read_exact(&mut payload).await.unwrap();
+        (head.kind, head.flags, payload)
+    }
+
+    #[test]
+    fn tracks_frame_boundaries() {
+        let mut frames = Frames::new(PREFACE_LEN);
+        frames.advance(&[0; PREFACE_LEN], |_| {});
+        assert!(frames.is_at_boundary());
+
+        let ping = frame(FRAME_PING, 0, 0, &PING_PAYLOAD);
+        frames.advance(&ping[..4], |_| {});
+        assert!(!frames.is_at_boundary());
+        frames.advance(&ping[4..12], |_| {});
+        assert!(!frames.is_at_boundary());
+        frames.advance(&ping[12..], |_| {});
+        assert!(frames.is_at_boundary());
+    }
+
+    #[tokio::test]
+    async fn filters_own_ping_acks() {
+        let (client, mut server) = duplex(1024);
+        let config = KeepAlive {
+            interval: Some(Duration::from_secs(60)),
+            ..KeepAlive::default()
+        };
+        let mut client = KeepAliveIo::new(client, config, true);
+
+        let foreign_ack = frame(FRAME_PING, FLAG_ACK, 0, b"12345678");
+        let settings = frame(FRAME_SETTINGS, 0, 0, &[]);
+
+        let mut inbound = frame(FRAME_PING, FLAG_ACK, 0, &PING_PAYLOAD);
+        inbound.extend_from_slice(&foreign_ack);
+        inbound.extend_from_slice(&settings);
+        server.write_all(&inbound).await.unwrap();
+        drop(server);
+
+        let mut read = Vec::new();
+        client.read_to_end(&mut read).await.unwrap();
+
+        let mut expected = foreign_ack;
+        expected.extend_from_slice(&settings);
+        assert_eq!(read, expected);
+    }
+
+    #[tokio::test]
+    async fn pings_only_after_settings() {
+        let (server, mut client) = duplex(1024);
+        let config = KeepAlive {
+            interval: Some(Duration::from_millis(10)),
+            timeout: Some(Duration::from_secs(60)),
+            while_idle: true,
+            ..KeepAlive::default()
+        };
+        let mut server = KeepAliveIo::new(server, config, false);
+
+        let read = Timeout::new(server.read(&mut [0; 16]), Duration::from_millis(100)).await;
+        assert!(read.is_err());
+
+        server
+            .write_all(&frame(FRAME_SETTINGS, 0, 0, &[]))
+            .await
+            .unwrap();
+        server.flush().await.unwrap();
+
+        assert_eq!(read_frame(&mut client).await.0, FRAME_SETTINGS);
+        assert_eq!(
+            read_frame(&mut client).await,
+            (FRAME_PING, 0, PING_PAYLOAD.to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn does_not_ping_idle_connections() {
+        let (server, mut client) = duplex(1024);
+        let config = KeepAlive {
+            interval: Some(Duration::from_millis(50)),
+            timeout: Some(Duration::from_secs(60)),
+            while_idle: false,
+            ..KeepAlive::default()
+        };
+        let mut server = KeepAliveIo::new(server, config, false);
+
+        server
+            .write_all(&frame(FRAME_SETTINGS, 0, 0, &[]))
+            .await
+            .unwrap();
+        server
+            .write_all(&frame(FRAME_HEADERS, 0, 1, &[]))
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let _ = server.read_to_end(&mut Vec::new()).await;
+        });
+
+        assert_eq!(read_frame(&mut client).await.0, FRAME_SETTINGS);
+        assert_eq!(read_frame(&mut client).await.0, FRAME_HEADERS);
+
+        // The stream was active, so it is pinged once.
+        assert_eq!(
+            read_frame(&mut client).await,
+            (FRAME_PING, 0, PING_PAYLOAD.to_vec())
+        );
+
+        let mut inbound = vec![0; PREFACE_LEN];
+        inbound.extend_from_slice(&frame(FRAME_PING, FLAG_ACK, 0, &PING_PAYLOAD));
+        client.write_all(&inbound).await.unwrap();
+
+        // The ack itself is not activity.
+        let next = Timeout::new(read_frame(&mut client), Duration::from_millis(300)).await;
+        assert!(next.is_err());
+    }
+
+    #[tokio::test]
+    async fn times_out_without_ping_ack() {
+        let (server, mut client) = duplex(1024);
+        let config = KeepAlive {
+            interval: Some(Duration::from_millis(50)),
+            timeout: Some(Duration::from_millis(50)),
+            while_idle: true,
+            ..KeepAlive::default()
+        };
+        let mut server = KeepAliveIo::new(server, config, false);
+
+        server
+            .write_all(&frame(FRAME_SETTINGS, 0, 0, &[]))
+            .await
+            .unwrap();
+
+        let error = server.read(&mut [0; 16]).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+
+        assert_eq!(read_frame(&mut client).await.0, FRAME_SETTINGS);
+        assert_eq!(read_frame(&mut client).await.0, FRAME_PING);
+    }
+}
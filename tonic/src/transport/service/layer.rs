@@ -1,7 +1,13 @@
 use super::either::Either;
+use futures_util::{try_future::MapErr, TryFutureExt};
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
 use tower::{
     layer::{util::Stack, Layer},
-    ServiceBuilder,
+    util::BoxService,
+    Service, ServiceBuilder,
 };
 pub(crate) trait ServiceBuilderExt<L> {
     fn layer_fn<F: Fn(S) -> Out, S, Out>(self, f: F) -> ServiceBuilder<Stack<LayerFn<F>, L>>;
@@ -78,3 +84,65 @@ where
         }
     }
 }
+
+/// A user supplied layer, type erased so it can be stored in a builder.
+pub(crate) type BoxLayer<Req, Res> = Arc<
+    dyn Layer<BoxService<Req, Res, crate::Error>, Service = BoxService<Req, Res, crate::Error>>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+pub(crate) fn box_layer<L, Req, Res>(layer: L) -> BoxLayer<Req, Res>
+where
+    L: Layer<BoxService<Req, Res, crate::Error>> + Send + Sync + 'static,
+    L::Service: Service<Req, Response = Res> + Send + 'static,
+    <L::Service as Service<Req>>::Future: Send + 'static,
+    <L::Service as Service<Req>>::Error: Into<crate::Error>,
+    Req: 'static,
+    Res: 'static,
+{
+    Arc::new(layer_fn(move |svc| {
+        BoxService::new(IntoError {
+            inner: layer.layer(svc),
+        })
+    }))
+}
+
+/// Wrap `svc` in `layers`, the first one being the outermost.
+pub(crate) fn apply_layers<S, Req, Res>(
+    layers: &[BoxLayer<Req, Res>],
+    svc: S,
+) -> BoxService<Req, Res, crate::Error>
+where
+    S: Service<Req, Response = Res> + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<crate::Error>,
+    Req: 'static,
+    Res: 'static,
+{
+    let svc = BoxService::new(IntoError { inner: svc });
+    layers.iter().rev().fold(svc, |svc, layer| layer.layer(svc))
+}
+
+struct IntoError<S> {
+    inner: S,
+}
+
+impl<S, Req> Service<Req> for IntoError<S>
+where
+    S: Service<Req>,
+    S::Error: Into<crate::Error>,
+{
+    type Response = S::Response;
+    type Error = crate::Error;
+    type Future = MapErr<S::Future, fn(S::Error) -> crate::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req).map_err(Into::into)
+    }
+}
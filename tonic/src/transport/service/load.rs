@@ -0,0 +1,294 @@
+use http::HeaderMap;
+use http_body::{Body as HttpBody, SizeHint};
+use std::{
+    fmt,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// How the load of a connection is measured for balancing.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum LoadKind {
+    /// The number of requests in flight.
+    PendingRequests,
+    /// The peak-EWMA of the response latency, scaled by the number of
+    /// requests in flight.
+    PeakEwma {
+        default_rtt: Duration,
+        decay: Duration,
+    },
+}
+
+impl Default for LoadKind {
+    fn default() -> Self {
+        LoadKind::PendingRequests
+    }
+}
+
+/// The load of a connection, lower is better.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub(crate) struct Cost(f64);
+
+/// Tracks the requests sent on a connection to estimate its load.
+#[derive(Debug)]
+pub(crate) struct LoadTracker {
+    kind: LoadKind,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    pending: usize,
+    rtt_ns: f64,
+    updated_at: Instant,
+}
+
+/// Marks a request as in flight until dropped.
+#[derive(Debug)]
+pub(crate) struct Handle {
+    kind: LoadKind,
+    sent_at: Instant,
+    responded: bool,
+    state: Arc<Mutex<State>>,
+}
+
+/// The body of a response received on a [`Channel`].
+///
+/// The request it answers counts towards the load of its connection until
+/// the body has been read to the end or dropped.
+///
+/// [`Channel`]: struct.Channel.html
+pub struct ResponseBody {
+    inner: hyper::Body,
+    handle: Option<Handle>,
+}
+
+impl LoadTracker {
+    pub(crate) fn new(kind: LoadKind) -> Self {
+        let rtt_ns = match kind {
+            LoadKind::PendingRequests => 0.0,
+            LoadKind::PeakEwma { default_rtt, .. } => nanos(default_rtt),
+        };
+
+        let state = State {
+            pending: 0,
+            rtt_ns,
+            updated_at: Instant::now(),
+        };
+
+        Self {
+            kind,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Record a request being sent.
+    pub(crate) fn start(&self) -> Handle {
+        self.state.lock().unwrap().pending += 1;
+
+        Handle {
+            kind: self.kind,
+            sent_at: Instant::now(),
+            responded: false,
+            state: self.state.clone(),
+        }
+    }
+
+    pub(crate) fn cost(&self) -> Cost {
+        let mut state = self.state.lock().unwrap();
+        let pending = state.pending as f64;
+
+        match self.kind {
+            LoadKind::PendingRequests => Cost(pending),
+            LoadKind::PeakEwma { decay, .. } => {
+                // Let the estimate decay towards zero while nothing completes.
+                let now = Instant::now();
+                let rtt_ns = state.update(0.0, now, decay);
+                Cost(rtt_ns * (pending + 1.0))
+            }
+        }
+    }
+}
+
+impl State {
+    /// Fold a latency sample into the estimate.
+    ///
+    /// Samples above the estimate replace it right away, while lower ones
+    /// only pull it down as time passes.
+    fn update(&mut self, rtt_ns: f64, now: Instant, decay: Duration) -> f64 {
+        if self.rtt_ns < rtt_ns {
+            self.rtt_ns = rtt_ns;
+        } else {
+            let elapsed = nanos(now.saturating_duration_since(self.updated_at));
+            let decay = (-elapsed / nanos(decay)).exp();
+            self.rtt_ns = self.rtt_ns * decay + rtt_ns * (1.0 - decay);
+        }
+
+        self.updated_at = now;
+        self.rtt_ns
+    }
+}
+
+impl Handle {
+    /// Record the response headers arriving.
+    ///
+    /// The latency is sampled here rather than once the response body ends,
+    /// so that long streams do not count as slow responses.
+    pub(crate) fn on_response(&mut self) {
+        if !self.responded {
+            self.responded = true;
+            if let Ok(mut state) = self.state.lock() {
+                self.sample(&mut state);
+            }
+        }
+    }
+
+    fn sample(&self, state: &mut State) {
+        if let LoadKind::PeakEwma { decay, .. } = self.kind {
+            let now = Instant::now();
+            let rtt_ns = nanos(now.saturating_duration_since(self.sent_at));
+            state.update(rtt_ns, now, decay);
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+
+        state.pending -= 1;
+
+        if !self.responded {
+            self.sample(&mut state);
+        }
+    }
+}
+
+impl ResponseBody {
+    pub(crate) fn new(inner: hyper::Body, handle: Handle) -> Self {
+        ResponseBody {
+            inner,
+            handle: Some(handle),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn empty() -> Self {
+        ResponseBody {
+            inner: hyper::Body::empty(),
+            handle: None,
+        }
+    }
+}
+
+impl HttpBody for ResponseBody {
+    type Data = hyper::Chunk;
+    type Error = hyper::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let res = futures_util::ready!(Pin::new(&mut self.inner).poll_data(cx));
+
+        if let Some(Err(_)) = res {
+            self.handle = None;
+        }
+
+        Poll::Ready(res)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let res = futures_util::ready!(Pin::new(&mut self.inner).poll_trailers(cx));
+        // The trailers end the stream.
+        self.handle = None;
+        Poll::Ready(res)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseBody").finish()
+    }
+}
+
+fn nanos(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1_000_000_000.0 + f64::from(d.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_pending_requests() {
+        let load = LoadTracker::new(LoadKind::PendingRequests);
+        assert_eq!(load.cost(), Cost(0.0));
+
+        let a = load.start();
+        let b = load.start();
+        assert_eq!(load.cost(), Cost(2.0));
+
+        drop(a);
+        drop(b);
+        assert_eq!(load.cost(), Cost(0.0));
+    }
+
+    #[test]
+    fn peak_ewma_prefers_faster_connections() {
+        let kind = LoadKind::PeakEwma {
+            default_rtt: Duration::from_millis(30),
+            decay: Duration::from_secs(10),
+        };
+        let fast = LoadTracker::new(kind);
+        let slow = LoadTracker::new(kind);
+
+        let now = Instant::now();
+        slow.state.lock().unwrap().update(
+            nanos(Duration::from_millis(500)),
+            now,
+            Duration::from_secs(10),
+        );
+
+        assert!(fast.cost() < slow.cost());
+
+        let _pending = fast.start();
+        assert!(fast.cost() < slow.cost());
+    }
+
+    #[tokio::test]
+    async fn response_body_keeps_request_in_flight() {
+        let load = LoadTracker::new(LoadKind::PendingRequests);
+
+        let mut handle = load.start();
+        handle.on_response();
+        let mut body = ResponseBody::new(hyper::Body::empty(), handle);
+        assert_eq!(load.cost(), Cost(1.0));
+
+        let data = futures_util::future::poll_fn(|cx| Pin::new(&mut body).poll_data(cx)).await;
+        assert!(data.is_none());
+        assert_eq!(load.cost(), Cost(1.0));
+
+        let trailers = futures_util::future::poll_fn(|cx| Pin::new(&mut body).poll_trailers(cx))
+            .await
+            .unwrap();
+        assert!(trailers.is_none());
+        assert_eq!(load.cost(), Cost(0.0));
+    }
+}
@@ -1,21 +1,43 @@
 mod add_origin;
+mod balance;
 mod connection;
+mod connectivity;
 mod connector;
 mod discover;
 mod either;
+mod fail_fast;
+mod grpc_timeout;
+mod hedge;
+mod in_process;
 mod io;
+mod keepalive;
 mod layer;
+mod load;
 mod reconnect;
+mod retry;
 mod router;
 #[cfg(feature = "tls")]
 mod tls;
 
 pub(crate) use self::add_origin::AddOrigin;
+pub(crate) use self::balance::Balancer;
 pub(crate) use self::connection::Connection;
-pub(crate) use self::connector::connector;
-pub(crate) use self::discover::ServiceList;
-pub(crate) use self::io::BoxedIo;
-pub(crate) use self::layer::{layer_fn, ServiceBuilderExt};
-pub(crate) use self::router::{Or, Routes};
+pub(crate) use self::connectivity::Connectivity;
+pub(crate) use self::connector::{connector, DefaultConnector, UDS_SCHEME};
+pub(crate) use self::discover::{DnsDiscover, DynamicServiceStream, ServiceList};
+pub(crate) use self::fail_fast::FailFast;
+pub(crate) use self::grpc_timeout::GrpcTimeoutLayer;
+pub(crate) use self::hedge::Hedge;
+pub use self::hedge::HedgingPolicy;
+pub(crate) use self::in_process::in_process;
+pub(crate) use self::io::{BoxedIo, ServerIo};
+pub(crate) use self::keepalive::KeepAlive;
+pub(crate) use self::layer::{apply_layers, box_layer, layer_fn, BoxLayer, ServiceBuilderExt};
+pub(crate) use self::load::LoadKind;
+pub use self::load::ResponseBody;
+pub(crate) use self::reconnect::Backoff;
+pub use self::retry::RetryPolicy;
+pub(crate) use self::retry::{Retry, RetryFuture};
+pub(crate) use self::router::{BoxRoute, Routes};
 #[cfg(feature = "tls")]
 pub(crate) use self::tls::{TlsAcceptor, TlsConnector};
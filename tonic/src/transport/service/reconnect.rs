@@ -1,14 +1,25 @@
-use crate::Error;
+use super::connectivity::Reporter;
+use crate::{transport::channel::ConnectivityState, Error};
 use pin_project::pin_project;
+use rand::Rng;
 use std::fmt;
 use std::{
+    cmp,
     future::Future,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
+use tokio::timer::{delay_for, Delay};
 use tower_make::MakeService;
 use tower_service::Service;
-use tracing::trace;
+use tracing::{debug, trace};
+
+/// Growth of the delay between reconnect attempts, from the gRPC connection
+/// backoff spec.
+const MULTIPLIER: f64 = 1.6;
+/// How much each delay is randomly spread, from the same spec.
+const JITTER: f64 = 0.2;
 
 pub(crate) struct Reconnect<M, Target>
 where
@@ -17,6 +28,8 @@ where
     mk_service: M,
     state: State<M::Future, M::Response>,
     target: Target,
+    backoff: Backoff,
+    reporter: Reporter,
 }
 
 #[derive(Debug)]
@@ -24,23 +37,77 @@ enum State<F, S> {
     Idle,
     Connecting(F),
     Connected(S),
+    /// Waiting to reconnect after connecting failed.
+    Backoff(Delay),
+}
+
+/// The delays between reconnect attempts, growing exponentially from
+/// `initial` up to `max`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// The delay before the next attempt.
+    fn next(&mut self) -> Duration {
+        let jitter = rand::thread_rng().gen_range(1.0 - JITTER, 1.0 + JITTER);
+        let delay = self.current.mul_f64(jitter);
+        self.current = cmp::min(self.current.mul_f64(MULTIPLIER), self.max);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
 }
 
 impl<M, Target> Reconnect<M, Target>
 where
     M: Service<Target>,
 {
-    pub(crate) fn new<S, Request>(initial_connection: S, mk_service: M, target: Target) -> Self
+    /// Create a `Reconnect` that starts from `initial_connection`, or
+    /// idle, connecting on the first `poll_ready`, when there is none.
+    pub(crate) fn new<S, Request>(
+        initial_connection: Option<S>,
+        mk_service: M,
+        target: Target,
+        backoff: Backoff,
+        reporter: Reporter,
+    ) -> Self
     where
         M: Service<Target, Response = S>,
         S: Service<Request>,
         Error: From<M::Error> + From<S::Error>,
         Target: Clone,
     {
+        let state = match initial_connection {
+            Some(conn) => {
+                reporter.set(ConnectivityState::Ready);
+                State::Connected(conn)
+            }
+            None => {
+                reporter.set(ConnectivityState::Idle);
+                State::Idle
+            }
+        };
+
         Reconnect {
             mk_service,
-            state: State::Connected(initial_connection),
+            state,
             target,
+            backoff,
+            reporter,
         }
     }
 }
@@ -58,7 +125,6 @@ where
     type Future = ResponseFuture<S::Future>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        let ret;
         let mut state;
 
         loop {
@@ -75,12 +141,15 @@ where
 
                     let fut = self.mk_service.make_service(self.target.clone());
                     self.state = State::Connecting(fut);
+                    self.reporter.set(ConnectivityState::Connecting);
                     continue;
                 }
                 State::Connecting(ref mut f) => {
                     trace!("poll_ready; connecting");
                     match Pin::new(f).poll(cx) {
                         Poll::Ready(Ok(service)) => {
+                            self.backoff.reset();
+                            self.reporter.set(ConnectivityState::Ready);
                             state = State::Connected(service);
                         }
                         Poll::Pending => {
@@ -88,10 +157,14 @@ where
                             return Poll::Pending;
                         }
                         Poll::Ready(Err(e)) => {
-                            trace!("poll_ready; error");
-                            state = State::Idle;
-                            ret = Err(e.into());
-                            break;
+                            let delay = self.backoff.next();
+                            debug!(
+                                "reconnect failed, retrying in {:?}: {}",
+                                delay,
+                                Error::from(e)
+                            );
+                            self.reporter.set(ConnectivityState::TransientFailure);
+                            state = State::Backoff(delay_for(delay));
                         }
                     }
                 }
@@ -108,17 +181,22 @@ where
                         }
                         Poll::Ready(Err(_)) => {
                             trace!("poll_ready; error");
+                            self.reporter.set(ConnectivityState::Idle);
                             state = State::Idle;
                         }
                     }
                 }
+                State::Backoff(ref mut delay) => {
+                    // Stay not ready so balancers pick another connection,
+                    // `FailFast` fails the request if there is none.
+                    trace!("poll_ready; backoff");
+                    futures_util::ready!(Pin::new(delay).poll(cx));
+                    state = State::Idle;
+                }
             }
 
             self.state = state;
         }
-
-        self.state = state;
-        Poll::Ready(ret)
     }
 
     fn call(&mut self, request: Request) -> Self::Future {
@@ -172,3 +250,23 @@ where
         self.project().inner.poll(cx).map_err(Into::into)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(3));
+
+        let first = backoff.next();
+        assert!(first >= Duration::from_millis(800) && first <= Duration::from_millis(1200));
+
+        for _ in 0..10 {
+            assert!(backoff.next() <= Duration::from_millis(3600));
+        }
+
+        backoff.reset();
+        assert!(backoff.next() <= Duration::from_millis(1200));
+    }
+}
@@ -0,0 +1,784 @@
+use super::load::ResponseBody;
+use crate::{
+    body::{BoxBody, BytesBuf},
+    request::{duration_to_grpc_timeout, try_parse_grpc_timeout, GRPC_TIMEOUT_HEADER},
+    Code, Status,
+};
+use bytes::{Bytes, IntoBuf};
+use futures_util::future::poll_fn;
+use http::{request::Parts, Extensions, HeaderMap, HeaderValue, Request, Response};
+use http_body::Body as HttpBody;
+use rand::Rng;
+use std::{
+    cmp,
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::timer::delay_for;
+use tower_service::Service;
+use tracing::debug;
+
+const GRPC_RETRY_PUSHBACK_HEADER: &str = "grpc-retry-pushback-ms";
+const GRPC_PREVIOUS_ATTEMPTS_HEADER: &str = "grpc-previous-rpc-attempts";
+
+/// The most attempts the gRPC retry spec allows.
+const MAX_ATTEMPTS: usize = 5;
+
+pub(crate) type RetryFuture =
+    Pin<Box<dyn Future<Output = Result<Response<ResponseBody>, crate::Error>> + Send + 'static>>;
+
+/// Configures how failed requests on a [`Channel`] are retried, following the
+/// [gRPC retry design].
+///
+/// A request is retried when it fails with one of the retryable codes before
+/// the server has sent response headers, or when it could not be sent at all,
+/// which counts as `Unavailable`. That is when no connection could be
+/// established or the server refused the stream. Other transport errors are
+/// returned to the caller, as the server may already have acted on the
+/// request. Once headers have been received the response is handed to the
+/// caller and never retried.
+///
+/// The request body is buffered so that it can be replayed, which allows
+/// retrying client streaming calls as long as the body fits the
+/// [`buffer_limit`].
+///
+/// ```
+/// # use tonic::transport::channel::RetryPolicy;
+/// # use tonic::Code;
+/// # use std::time::Duration;
+/// let policy = RetryPolicy::new()
+///     .max_attempts(4)
+///     .initial_backoff(Duration::from_millis(50))
+///     .retryable_codes(vec![Code::Unavailable, Code::ResourceExhausted])
+///     .throttle(10, 0.1);
+/// ```
+///
+/// [`Channel`]: struct.Channel.html
+/// [gRPC retry design]: https://github.com/grpc/proposal/blob/master/A6-client-retries.md
+/// [`buffer_limit`]: #method.buffer_limit
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    backoff_multiplier: f64,
+    retryable_codes: Vec<Code>,
+    buffer_limit: usize,
+    throttle: Option<(u32, f64)>,
+}
+
+impl RetryPolicy {
+    /// Create a policy that makes up to three attempts for requests failing
+    /// with `Unavailable`.
+    pub fn new() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            backoff_multiplier: 2.0,
+            retryable_codes: vec![Code::Unavailable],
+            buffer_limit: 256 * 1024,
+            throttle: None,
+        }
+    }
+
+    /// Set the most attempts made for a request, including the first one.
+    ///
+    /// Values above 5 are treated as 5. Default is 3.
+    pub fn max_attempts(self, max_attempts: usize) -> Self {
+        RetryPolicy {
+            max_attempts: cmp::min(max_attempts, MAX_ATTEMPTS),
+            ..self
+        }
+    }
+
+    /// Set the upper bound of the randomized delay before the first retry.
+    ///
+    /// Default is 100 milliseconds.
+    pub fn initial_backoff(self, backoff: Duration) -> Self {
+        RetryPolicy {
+            initial_backoff: backoff,
+            ..self
+        }
+    }
+
+    /// Set the most the upper bound of the randomized delay may grow to.
+    ///
+    /// Default is 1 second.
+    pub fn max_backoff(self, backoff: Duration) -> Self {
+        RetryPolicy {
+            max_backoff: backoff,
+            ..self
+        }
+    }
+
+    /// Set what the upper bound of the randomized delay is multiplied by
+    /// after every retry.
+    ///
+    /// Values that are not positive, including NaN, are treated as 1, which
+    /// keeps the delay constant. Default is 2.
+    pub fn backoff_multiplier(self, multiplier: f64) -> Self {
+        let multiplier = if multiplier > 0.0 {
+            multiplier.min(std::f64::MAX)
+        } else {
+            1.0
+        };
+
+        RetryPolicy {
+            backoff_multiplier: multiplier,
+            ..self
+        }
+    }
+
+    /// Set the status codes that are retried.
+    ///
+    /// Default is `Unavailable` only.
+    pub fn retryable_codes(self, codes: Vec<Code>) -> Self {
+        RetryPolicy {
+            retryable_codes: codes,
+            ..self
+        }
+    }
+
+    /// Set how many bytes of a request body are buffered for replaying it.
+    ///
+    /// Requests with a larger body are not retried once the limit has been
+    /// passed. Default is 256 KiB.
+    pub fn buffer_limit(self, limit: usize) -> Self {
+        RetryPolicy {
+            buffer_limit: limit,
+            ..self
+        }
+    }
+
+    /// Throttle retries once too many requests fail.
+    ///
+    /// Every channel keeps `max_tokens` tokens. A failed attempt takes one
+    /// and a successful one returns `token_ratio`. No retries are made while
+    /// half of the tokens or fewer are left. Retries are not throttled by
+    /// default.
+    pub fn throttle(self, max_tokens: u32, token_ratio: f64) -> Self {
+        RetryPolicy {
+            throttle: Some((max_tokens, token_ratio)),
+            ..self
+        }
+    }
+
+    fn is_retryable(&self, code: Code) -> bool {
+        self.retryable_codes.contains(&code)
+    }
+
+    /// Pick the delay before the next retry, growing the backoff.
+    fn next_delay(&self, backoff: &mut Duration) -> Duration {
+        let max = backoff.as_secs_f64();
+        // Grow in floating point, a `Duration` would overflow for large
+        // multipliers before being capped.
+        let next = max * self.backoff_multiplier;
+        *backoff = if next < self.max_backoff.as_secs_f64() {
+            Duration::from_secs_f64(next)
+        } else {
+            self.max_backoff
+        };
+
+        if max > 0.0 {
+            Duration::from_secs_f64(rand::thread_rng().gen_range(0.0, max))
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The retry state shared by all clones of a channel.
+#[derive(Debug, Clone)]
+pub(crate) struct Retry {
+    policy: Arc<RetryPolicy>,
+    throttle: Option<Arc<Throttle>>,
+}
+
+impl Retry {
+    pub(crate) fn new(policy: RetryPolicy) -> Self {
+        let throttle = policy
+            .throttle
+            .map(|(max_tokens, token_ratio)| Arc::new(Throttle::new(max_tokens, token_ratio)));
+
+        Retry {
+            policy: Arc::new(policy),
+            throttle,
+        }
+    }
+
+    /// Send `request` on `svc`, which must be ready, retrying it on other
+    /// clones of `svc` as needed.
+    pub(crate) fn call<S>(&self, svc: &mut S, request: Request<BoxBody>) -> RetryFuture
+    where
+        S: Service<Request<BoxBody>, Response = Response<ResponseBody>, Error = crate::Error>
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        let (mut parts, body) = request.into_parts();
+        let deadline = match try_parse_grpc_timeout(&parts.headers) {
+            Ok(timeout) => timeout.map(|timeout| Instant::now() + timeout),
+            Err(_) => None,
+        };
+
+        let replay = Replay::new(body, self.policy.buffer_limit);
+        let mut first = attempt_request(&parts, replay.attempt(), 0, None);
+        // Only the first attempt gets the extensions, they cannot be cloned.
+        *first.extensions_mut() = mem::replace(&mut parts.extensions, Extensions::new());
+        let first = svc.call(first);
+
+        Box::pin(run(
+            svc.clone(),
+            self.clone(),
+            parts,
+            replay,
+            deadline,
+            first,
+        ))
+    }
+}
+
+async fn run<S>(
+    mut svc: S,
+    retry: Retry,
+    parts: Parts,
+    replay: Replay,
+    deadline: Option<Instant>,
+    first: S::Future,
+) -> Result<Response<ResponseBody>, crate::Error>
+where
+    S: Service<Request<BoxBody>, Response = Response<ResponseBody>, Error = crate::Error>,
+{
+    let policy = &retry.policy;
+    let mut backoff = policy.initial_backoff;
+    let mut attempts = 1;
+    let mut res = first.await;
+
+    loop {
+        let (code, pushback) = match &res {
+            Ok(response) => match Status::from_header_map(response.headers()) {
+                // A trailers-only response, the call failed before it began.
+                Some(status) if status.code() != Code::Ok => {
+                    (status.code(), parse_pushback(response.headers()))
+                }
+                _ => {
+                    if let Some(throttle) = &retry.throttle {
+                        throttle.on_success();
+                    }
+                    return res;
+                }
+            },
+            Err(error) => match unsent_code(&**error) {
+                Some(code) => (code, None),
+                None => return res,
+            },
+        };
+
+        if !policy.is_retryable(code) {
+            return res;
+        }
+
+        let throttled = match &retry.throttle {
+            Some(throttle) => !throttle.on_failure(),
+            None => false,
+        };
+
+        if throttled || attempts >= policy.max_attempts || !replay.is_replayable() {
+            return res;
+        }
+
+        let delay = match pushback {
+            Some(Pushback::Stop) => return res,
+            Some(Pushback::After(delay)) => {
+                backoff = policy.initial_backoff;
+                delay
+            }
+            None => policy.next_delay(&mut backoff),
+        };
+
+        let timeout = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now() + delay) {
+                Some(timeout) if timeout > Duration::from_secs(0) => Some(timeout),
+                _ => return res,
+            },
+            None => None,
+        };
+
+        debug!(
+            "retrying request after {:?}, attempt {}",
+            delay,
+            attempts + 1
+        );
+        delay_for(delay).await;
+
+        poll_fn(|cx| svc.poll_ready(cx)).await?;
+        let request = attempt_request(&parts, replay.attempt(), attempts, timeout);
+        res = svc.call(request).await;
+        attempts += 1;
+    }
+}
+
+/// The code of an attempt that failed before it was sent, or `None` if the
+/// server may have seen it.
+///
+/// Requests that failed fast, or found no connection, or were refused by
+/// the server, never reached a handler and are safe to send again.
+fn unsent_code(error: &(dyn std::error::Error + 'static)) -> Option<Code> {
+    let mut cause = Some(error);
+
+    while let Some(error) = cause {
+        if let Some(status) = error.downcast_ref::<Status>() {
+            return Some(status.code());
+        }
+
+        if let Some(error) = error.downcast_ref::<hyper::Error>() {
+            // Canceled requests were dropped from the queue of a connection
+            // that closed before writing them.
+            if error.is_connect() || error.is_canceled() {
+                return Some(Code::Unavailable);
+            }
+        }
+
+        if let Some(error) = error.downcast_ref::<h2::Error>() {
+            if error.reason() == Some(h2::Reason::REFUSED_STREAM) {
+                return Some(Code::Unavailable);
+            }
+        }
+
+        cause = error.source();
+    }
+
+    None
+}
+
+/// Build the request for one attempt from the parts of the original.
+pub(super) fn attempt_request<B>(
+    parts: &Parts,
+    body: B,
+    previous_attempts: usize,
+    timeout: Option<Duration>,
+) -> Request<BoxBody>
+where
+    B: HttpBody<Data = BytesBuf, Error = Status> + Send + Sync + 'static,
+{
+    let mut request = Request::new(BoxBody::new(body));
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers.clone();
+
+    if previous_attempts > 0 {
+        request.headers_mut().insert(
+            GRPC_PREVIOUS_ATTEMPTS_HEADER,
+            HeaderValue::from(previous_attempts as u64),
+        );
+    }
+
+    if let Some(timeout) = timeout {
+        if let Ok(value) = HeaderValue::from_str(&duration_to_grpc_timeout(timeout)) {
+            request.headers_mut().insert(GRPC_TIMEOUT_HEADER, value);
+        }
+    }
+
+    request
+}
+
+#[derive(Debug, PartialEq)]
+enum Pushback {
+    /// The server asked not to retry.
+    Stop,
+    /// The server asked to retry after a delay.
+    After(Duration),
+}
+
+fn parse_pushback(headers: &HeaderMap) -> Option<Pushback> {
+    let value = headers.get(GRPC_RETRY_PUSHBACK_HEADER)?;
+
+    let pushback = value
+        .to_str()
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(|ms| Pushback::After(Duration::from_millis(ms)))
+        // Negative or malformed values mean the server does not want a retry.
+        .unwrap_or(Pushback::Stop);
+
+    Some(pushback)
+}
+
+/// The retry throttling token bucket.
+#[derive(Debug)]
+struct Throttle {
+    max_tokens: f64,
+    token_ratio: f64,
+    tokens: Mutex<f64>,
+}
+
+impl Throttle {
+    fn new(max_tokens: u32, token_ratio: f64) -> Self {
+        let max_tokens = f64::from(max_tokens);
+
+        Throttle {
+            max_tokens,
+            token_ratio,
+            tokens: Mutex::new(max_tokens),
+        }
+    }
+
+    fn on_success(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + self.token_ratio).min(self.max_tokens);
+    }
+
+    /// Record a failed attempt, returning whether retries are still allowed.
+    fn on_failure(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens - 1.0).max(0.0);
+        *tokens > self.max_tokens / 2.0
+    }
+}
+
+/// A request body that is recorded as it is sent, so that it can be sent
+/// again for a retry.
+#[derive(Clone)]
+struct Replay {
+    shared: Arc<Mutex<Shared>>,
+}
+
+struct Shared {
+    body: BoxBody,
+    chunks: Vec<Bytes>,
+    buffered: usize,
+    limit: usize,
+    is_replayable: bool,
+    /// Bumped for every attempt, so that bodies of earlier attempts that
+    /// are still being polled no longer take data.
+    attempt: u64,
+}
+
+impl Replay {
+    fn new(body: BoxBody, limit: usize) -> Self {
+        let shared = Shared {
+            body,
+            chunks: Vec::new(),
+            buffered: 0,
+            limit,
+            is_replayable: true,
+            attempt: 0,
+        };
+
+        Replay {
+            shared: Arc::new(Mutex::new(shared)),
+        }
+    }
+
+    fn is_replayable(&self) -> bool {
+        self.shared.lock().unwrap().is_replayable
+    }
+
+    /// Create the body for the next attempt, replaying what was sent so far.
+    fn attempt(&self) -> ReplayBody {
+        let mut shared = self.shared.lock().unwrap();
+        shared.attempt += 1;
+
+        ReplayBody {
+            shared: self.shared.clone(),
+            attempt: shared.attempt,
+            pos: 0,
+        }
+    }
+}
+
+struct ReplayBody {
+    shared: Arc<Mutex<Shared>>,
+    attempt: u64,
+    pos: usize,
+}
+
+impl HttpBody for ReplayBody {
+    type Data = BytesBuf;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+        let mut shared = me.shared.lock().unwrap();
+
+        if shared.attempt != me.attempt {
+            return Poll::Ready(None);
+        }
+
+        if me.pos < shared.chunks.len() {
+            me.pos += 1;
+            return Poll::Ready(Some(Ok(shared.chunks[me.pos - 1].clone().into_buf())));
+        }
+
+        let chunk = match futures_util::ready!(Pin::new(&mut shared.body).poll_data(cx)) {
+            Some(Ok(chunk)) => chunk,
+            other => return Poll::Ready(other),
+        };
+        let bytes = chunk.get_ref().slice_from(chunk.position() as usize);
+
+        if shared.is_replayable {
+            shared.buffered += bytes.len();
+
+            if shared.buffered > shared.limit {
+                shared.is_replayable = false;
+                shared.chunks.clear();
+            } else {
+                shared.chunks.push(bytes.clone());
+                me.pos += 1;
+            }
+        }
+
+        Poll::Ready(Some(Ok(bytes.into_buf())))
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        // gRPC requests never have trailers.
+        Poll::Ready(Ok(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pushback() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_pushback(&headers), None);
+
+        headers.insert(GRPC_RETRY_PUSHBACK_HEADER, HeaderValue::from_static("250"));
+        assert_eq!(
+            parse_pushback(&headers),
+            Some(Pushback::After(Duration::from_millis(250)))
+        );
+
+        headers.insert(GRPC_RETRY_PUSHBACK_HEADER, HeaderValue::from_static("-1"));
+        assert_eq!(parse_pushback(&headers), Some(Pushback::Stop));
+    }
+
+    #[test]
+    fn throttle_stops_retries_below_half() {
+        let throttle = Throttle::new(4, 0.5);
+
+        assert!(throttle.on_failure());
+        assert!(!throttle.on_failure());
+
+        throttle.on_success();
+        throttle.on_success();
+        throttle.on_success();
+        assert!(throttle.on_failure());
+    }
+
+    #[test]
+    fn backoff_is_bounded() {
+        let policy = RetryPolicy::new()
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(300));
+        let mut backoff = policy.initial_backoff;
+
+        for &max in &[100, 200, 300, 300] {
+            assert!(policy.next_delay(&mut backoff) < Duration::from_millis(max));
+        }
+    }
+
+    #[test]
+    fn backoff_multiplier_is_clamped() {
+        for &multiplier in &[std::f64::NAN, -2.0, 0.0, 1e300, std::f64::INFINITY] {
+            let policy = RetryPolicy::new()
+                .initial_backoff(Duration::from_secs(1))
+                .max_backoff(Duration::from_secs(10))
+                .backoff_multiplier(multiplier);
+            let mut backoff = policy.initial_backoff;
+
+            for _ in 0..4 {
+                policy.next_delay(&mut backoff);
+                assert!(backoff <= Duration::from_secs(10));
+            }
+        }
+    }
+
+    struct Once(Option<Bytes>);
+
+    impl HttpBody for Once {
+        type Data = BytesBuf;
+        type Error = Status;
+
+        fn poll_data(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            Poll::Ready(self.0.take().map(|bytes| Ok(bytes.into_buf())))
+        }
+
+        fn poll_trailers(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+            Poll::Ready(Ok(None))
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_request_body() {
+        let body = BoxBody::new(Once(Some(Bytes::from("hello"))));
+        let replay = Replay::new(body, 1024);
+
+        let mut first = replay.attempt();
+        let chunk = poll_fn(|cx| Pin::new(&mut first).poll_data(cx)).await;
+        assert_eq!(chunk.unwrap().unwrap().into_inner(), Bytes::from("hello"));
+
+        let mut second = replay.attempt();
+        let chunk = poll_fn(|cx| Pin::new(&mut second).poll_data(cx)).await;
+        assert_eq!(chunk.unwrap().unwrap().into_inner(), Bytes::from("hello"));
+        assert!(poll_fn(|cx| Pin::new(&mut second).poll_data(cx))
+            .await
+            .is_none());
+
+        // The first attempt is over and does not take data anymore.
+        assert!(poll_fn(|cx| Pin::new(&mut first).poll_data(cx))
+            .await
+            .is_none());
+    }
+
+    /// Fails the first request with a trailers-only `Unavailable` response
+    /// and answers the following ones with an empty success.
+    #[derive(Clone, Default)]
+    struct Mock {
+        seen: Arc<Mutex<Vec<HeaderMap>>>,
+    }
+
+    impl Service<Request<BoxBody>> for Mock {
+        type Response = Response<ResponseBody>;
+        type Error = crate::Error;
+        type Future = futures_util::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+            let mut seen = self.seen.lock().unwrap();
+            seen.push(request.headers().clone());
+
+            let mut response = Response::new(ResponseBody::empty());
+            if seen.len() == 1 {
+                response
+                    .headers_mut()
+                    .insert("grpc-status", HeaderValue::from_static("14"));
+            }
+
+            futures_util::future::ready(Ok(response))
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_trailers_only_unavailable() {
+        let retry = Retry::new(RetryPolicy::new().initial_backoff(Duration::from_millis(1)));
+        let mut svc = Mock::default();
+
+        let request = Request::new(BoxBody::new(Once(Some(Bytes::from("hello")))));
+        let response = retry.call(&mut svc, request).await.unwrap();
+        assert!(Status::from_header_map(response.headers()).is_none());
+
+        let seen = svc.seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert!(seen[0].get(GRPC_PREVIOUS_ATTEMPTS_HEADER).is_none());
+        assert_eq!(seen[1][GRPC_PREVIOUS_ATTEMPTS_HEADER], "1");
+    }
+
+    #[test]
+    fn only_unsent_errors_are_retried() {
+        let failed_fast = Status::new(Code::Unavailable, "connection is in transient failure");
+        assert_eq!(unsent_code(&failed_fast), Some(Code::Unavailable));
+
+        let refused = h2::Error::from(h2::Reason::REFUSED_STREAM);
+        assert_eq!(unsent_code(&refused), Some(Code::Unavailable));
+
+        let reset = h2::Error::from(h2::Reason::CANCEL);
+        assert_eq!(unsent_code(&reset), None);
+
+        let io = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert_eq!(unsent_code(&io), None);
+    }
+
+    /// Fails the first request with `error` and answers the following ones
+    /// with an empty success.
+    #[derive(Clone)]
+    struct Failing {
+        error: fn() -> crate::Error,
+        calls: Arc<Mutex<usize>>,
+    }
+
+    impl Service<Request<BoxBody>> for Failing {
+        type Response = Response<ResponseBody>;
+        type Error = crate::Error;
+        type Future = futures_util::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: Request<BoxBody>) -> Self::Future {
+            let mut calls = self.calls.lock().unwrap();
+            *calls += 1;
+
+            if *calls == 1 {
+                futures_util::future::ready(Err((self.error)()))
+            } else {
+                futures_util::future::ready(Ok(Response::new(ResponseBody::empty())))
+            }
+        }
+    }
+
+    async fn calls_after_failing_with(error: fn() -> crate::Error) -> (bool, usize) {
+        let retry = Retry::new(RetryPolicy::new().initial_backoff(Duration::from_millis(1)));
+        let mut svc = Failing {
+            error,
+            calls: Arc::new(Mutex::new(0)),
+        };
+
+        let request = Request::new(BoxBody::new(Once(Some(Bytes::from("hello")))));
+        let ok = retry.call(&mut svc, request).await.is_ok();
+        let calls = *svc.calls.lock().unwrap();
+        (ok, calls)
+    }
+
+    #[tokio::test]
+    async fn retries_requests_that_were_not_sent() {
+        let (ok, calls) = calls_after_failing_with(|| {
+            Status::new(Code::Unavailable, "connection is in transient failure").into()
+        })
+        .await;
+        assert!(ok);
+        assert_eq!(calls, 2);
+    }
+
+    #[tokio::test]
+    async fn returns_errors_after_sending() {
+        let (ok, calls) = calls_after_failing_with(|| {
+            std::io::Error::from(std::io::ErrorKind::ConnectionReset).into()
+        })
+        .await;
+        assert!(!ok);
+        assert_eq!(calls, 1);
+    }
+}
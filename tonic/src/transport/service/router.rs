@@ -1,129 +1,132 @@
-use futures_util::{
-    future::Either,
-    try_future::{MapErr, TryFutureExt},
-};
+use crate::body::BoxBody;
+use futures_util::{future, TryFutureExt};
+use http::{Request, Response};
+use hyper::Body;
 use std::{
+    collections::HashMap,
     fmt,
-    sync::Arc,
+    future::Future,
+    pin::Pin,
     task::{Context, Poll},
 };
 use tower_service::Service;
 
-#[derive(Debug)]
-pub(crate) struct Routes<A, B, Request> {
-    routes: Or<A, B, Request>,
-}
+type BoxFuture =
+    Pin<Box<dyn Future<Output = Result<Response<BoxBody>, crate::Error>> + Send + 'static>>;
 
-impl<A, B, Request> Routes<A, B, Request> {
-    pub(crate) fn new(
-        predicate: impl Fn(&Request) -> bool + Send + Sync + 'static,
-        a: A,
-        b: B,
-    ) -> Self {
-        let routes = Or::new(predicate, a, b);
-        Self { routes }
-    }
+/// Routes requests to services by the `Service-Name` in their path.
+#[derive(Clone, Default)]
+pub(crate) struct Routes {
+    routes: HashMap<String, BoxRoute>,
 }
 
-impl<A, B, Request> Routes<A, B, Request> {
-    pub(crate) fn push<C>(
-        self,
-        predicate: impl Fn(&Request) -> bool + Send + Sync + 'static,
-        route: C,
-    ) -> Routes<C, Or<A, B, Request>, Request> {
-        let routes = Or::new(predicate, route, self.routes);
-        Routes { routes }
+impl Routes {
+    /// Route requests for `name` to `svc`, replacing any service previously
+    /// added under that name.
+    pub(crate) fn insert(&mut self, name: String, svc: BoxRoute) {
+        self.routes.insert(name, svc);
     }
 }
 
-impl<A, B, Request> Service<Request> for Routes<A, B, Request>
-where
-    A: Service<Request>,
-    A::Future: Send + 'static,
-    A::Error: Into<crate::Error>,
-    B: Service<Request, Response = A::Response>,
-    B::Future: Send + 'static,
-    B::Error: Into<crate::Error>,
-{
-    type Response = A::Response;
+impl Service<Request<Body>> for Routes {
+    type Response = Response<BoxBody>;
     type Error = crate::Error;
-    type Future = <Or<A, B, Request> as Service<Request>>::Future;
+    type Future = BoxFuture;
 
     fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         Ok(()).into()
     }
 
-    fn call(&mut self, req: Request) -> Self::Future {
-        self.routes.call(req)
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let route = service_name(req.uri().path()).and_then(|name| self.routes.get_mut(name));
+
+        match route {
+            Some(route) => route.0.call_boxed(req),
+            None => Box::pin(future::ok(
+                http::Response::builder()
+                    .status(200)
+                    .header("grpc-status", "12")
+                    .body(BoxBody::empty())
+                    .unwrap(),
+            )),
+        }
     }
 }
 
-impl<A: Clone, B: Clone, Request> Clone for Routes<A, B, Request> {
-    fn clone(&self) -> Self {
-        Self {
-            routes: self.routes.clone(),
-        }
+impl fmt::Debug for Routes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Routes")
+            .field("services", &self.routes.keys())
+            .finish()
     }
 }
 
-#[doc(hidden)]
-pub struct Or<A, B, Request> {
-    predicate: Arc<dyn Fn(&Request) -> bool + Send + Sync + 'static>,
-    a: A,
-    b: B,
+/// The `Service-Name` of a `/{Service-Name}/{method name}` path.
+fn service_name(path: &str) -> Option<&str> {
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let path = &path[1..];
+    path.find('/').map(|end| &path[..end])
 }
 
-impl<A, B, Request> Or<A, B, Request> {
-    pub(crate) fn new<F>(predicate: F, a: A, b: B) -> Self
+/// A type erased service that can be cloned for every connection.
+pub(crate) struct BoxRoute(Box<dyn CloneService + Send + 'static>);
+
+impl BoxRoute {
+    pub(crate) fn new<S>(svc: S) -> Self
     where
-        F: Fn(&Request) -> bool + Send + Sync + 'static,
+        S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<crate::Error> + Send,
     {
-        let predicate = Arc::new(predicate);
-        Self { predicate, a, b }
+        BoxRoute(Box::new(svc))
+    }
+}
+
+impl Clone for BoxRoute {
+    fn clone(&self) -> Self {
+        BoxRoute(self.0.clone_boxed())
     }
 }
 
-impl<A, B, Request> Service<Request> for Or<A, B, Request>
+trait CloneService {
+    fn call_boxed(&mut self, req: Request<Body>) -> BoxFuture;
+
+    fn clone_boxed(&self) -> Box<dyn CloneService + Send + 'static>;
+}
+
+impl<S> CloneService for S
 where
-    A: Service<Request>,
-    A::Future: Send + 'static,
-    A::Error: Into<crate::Error>,
-    B: Service<Request, Response = A::Response>,
-    B::Future: Send + 'static,
-    B::Error: Into<crate::Error>,
+    S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<crate::Error> + Send,
 {
-    type Response = A::Response;
-    type Error = crate::Error;
-    type Future = Either<
-        MapErr<A::Future, fn(A::Error) -> crate::Error>,
-        MapErr<B::Future, fn(B::Error) -> crate::Error>,
-    >;
-
-    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Ok(()).into()
+    fn call_boxed(&mut self, req: Request<Body>) -> BoxFuture {
+        Box::pin(Service::call(self, req).map_err(|e| e.into()))
     }
 
-    fn call(&mut self, req: Request) -> Self::Future {
-        if (self.predicate)(&req) {
-            Either::Left(self.a.call(req).map_err(|e| e.into()))
-        } else {
-            Either::Right(self.b.call(req).map_err(|e| e.into()))
-        }
+    fn clone_boxed(&self) -> Box<dyn CloneService + Send + 'static> {
+        Box::new(self.clone())
     }
 }
 
-impl<A: Clone, B: Clone, Request> Clone for Or<A, B, Request> {
-    fn clone(&self) -> Self {
-        Self {
-            predicate: self.predicate.clone(),
-            a: self.a.clone(),
-            b: self.b.clone(),
-        }
-    }
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl<A, B, Request> fmt::Debug for Or<A, B, Request> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Or {{ .. }}")
+    #[test]
+    fn service_name_matches_whole_segment() {
+        assert_eq!(
+            service_name("/helloworld.Greeter/SayHello"),
+            Some("helloworld.Greeter")
+        );
+        assert_eq!(
+            service_name("/helloworld.GreeterV2/SayHello"),
+            Some("helloworld.GreeterV2")
+        );
+        assert_eq!(service_name("/helloworld.Greeter"), None);
+        assert_eq!(service_name("helloworld.Greeter/SayHello"), None);
     }
 }
@@ -11,7 +11,6 @@ use openssl_probe;
 #[cfg(feature = "rustls-roots")]
 use rustls_native_certs;
 use std::{fmt, sync::Arc};
-use tokio::net::TcpStream;
 #[cfg(feature = "rustls")]
 use tokio_rustls::{
     rustls::{ClientConfig, NoClientAuth, ServerConfig, Session},
@@ -148,12 +147,14 @@ impl TlsConnector {
         })
     }
 
-    pub(crate) async fn connect(&self, io: TcpStream) -> Result<BoxedIo, crate::Error> {
+    pub(crate) async fn connect(&self, io: BoxedIo) -> Result<BoxedIo, crate::Error> {
         let tls_io = match &self.inner {
             #[cfg(feature = "openssl")]
             Connector::Openssl(connector) => {
                 let config = connector.configure()?;
-                let tls = tokio_openssl::connect(config, &self.domain, io).await?;
+                let tls = tokio_openssl::connect(config, &self.domain, io)
+                    .await
+                    .map_err(|e| e.to_string())?;
 
                 match tls.ssl().selected_alpn_protocol() {
                     Some(b) if b == b"h2" => tracing::trace!("HTTP/2 succesfully negotiated."),
@@ -304,29 +305,73 @@ impl TlsAcceptor {
         })
     }
 
-    pub(crate) async fn connect(&self, io: TcpStream) -> Result<BoxedIo, crate::Error> {
-        let io = match &self.inner {
+    /// Accept a TLS session on `io`, returning it along with the
+    /// certificates the client presented, if any.
+    pub(crate) async fn connect(
+        &self,
+        io: BoxedIo,
+    ) -> Result<(BoxedIo, Option<Vec<Certificate>>), crate::Error> {
+        let res = match &self.inner {
             #[cfg(feature = "openssl")]
             Acceptor::Openssl(acceptor) => {
-                let tls = tokio_openssl::accept(&acceptor, io).await?;
-                BoxedIo::new(tls)
+                let tls = tokio_openssl::accept(&acceptor, io)
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                // On the server side the chain does not include the leaf.
+                let ssl = tls.ssl();
+                let certs = ssl.peer_certificate().map(|leaf| {
+                    let chain = ssl.peer_cert_chain().into_iter().flatten();
+                    std::iter::once(&*leaf)
+                        .chain(chain)
+                        .filter_map(|cert| cert.to_pem().ok())
+                        .map(Certificate::from_pem)
+                        .collect()
+                });
+
+                (BoxedIo::new(tls), certs)
             }
 
             #[cfg(feature = "rustls")]
             Acceptor::Rustls(config) => {
                 let acceptor = RustlsAcceptor::from(config.clone());
                 let tls = acceptor.accept(io).await?;
-                BoxedIo::new(tls)
+
+                let (_, session) = tls.get_ref();
+                let certs = session.get_peer_certificates().map(|certs| {
+                    certs
+                        .iter()
+                        .map(|cert| Certificate::from_pem(der_to_pem(&cert.0)))
+                        .collect()
+                });
+
+                (BoxedIo::new(tls), certs)
             }
 
             #[allow(unreachable_patterns)]
             _ => unreachable!("Reached a tls config point with neither feature enabled!"),
         };
 
-        Ok(io)
+        Ok(res)
     }
 }
 
+/// Wrap a DER encoded certificate in PEM armor.
+#[cfg(feature = "rustls")]
+fn der_to_pem(der: &[u8]) -> String {
+    let encoded = base64::encode(der);
+    let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+
+    for line in encoded.as_bytes().chunks(64) {
+        // base64 output is ASCII.
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
 impl fmt::Debug for TlsAcceptor {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("TlsAcceptor")
@@ -30,6 +30,16 @@ impl Certificate {
         let pem = pem.as_ref().into();
         Self { pem }
     }
+
+    /// Get a reference to the PEM encoded certificate.
+    pub fn get_ref(&self) -> &[u8] {
+        &self.pem
+    }
+
+    /// Consumes `self`, returning the PEM encoded certificate.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.pem
+    }
 }
 
 impl Identity {